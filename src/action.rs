@@ -0,0 +1,412 @@
+use crate::error::RuntimeError;
+use crate::fim::{FimOptions, FimTemplate};
+use crate::ollama::GenerateRequest;
+use crate::AiCoder;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Lines of surrounding code read from the file when the caller sends none.
+pub const CONTEXT_LINES: usize = 30;
+
+/// Lines on each side of the cursor sent as fill-in-the-middle context.
+pub const FIM_CONTEXT_LINES: usize = 100;
+
+/// Zero-based line and character offset, as used by LSP. How characters
+/// are counted depends on the [`PositionEncoding`].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+/// What `Position::character` counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// UTF-16 code units, LSP's default.
+    Utf16,
+    /// Unicode scalar values, used by `ai-coder action`.
+    #[default]
+    Utf32,
+}
+
+impl PositionEncoding {
+    fn width(self, ch: char) -> usize {
+        match self {
+            PositionEncoding::Utf16 => ch.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SurroundingContext {
+    #[serde(default)]
+    pub before: String,
+    #[serde(default)]
+    pub after: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionKind {
+    /// Rewrite the selection according to `instruction`.
+    #[default]
+    Edit,
+    /// Fill in code at the cursor (`range.start`) from the surrounding code.
+    Complete,
+}
+
+/// One request from an editor. `selection` and `context` are read from
+/// `file` when omitted.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ActionRequest {
+    /// Echoed back so callers can match responses to requests.
+    #[serde(default)]
+    pub id: Option<serde_json::Value>,
+    #[serde(default)]
+    pub kind: ActionKind,
+    pub file: PathBuf,
+    pub range: Range,
+    #[serde(default)]
+    pub instruction: String,
+    pub selection: Option<String>,
+    pub context: Option<SurroundingContext>,
+    pub language: Option<String>,
+    /// Fill-in-the-middle format for `complete`; detected from the model name by default.
+    pub fim_template: Option<FimTemplate>,
+}
+
+/// Replace `range` in `file` with `new_text`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActionResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    pub file: PathBuf,
+    pub range: Range,
+    pub new_text: String,
+    pub model: String,
+}
+
+/// Byte offset of `position` in `text`, if it lies within the text. A
+/// position inside a UTF-16 surrogate pair moves to the next character.
+fn offset(text: &str, position: Position, encoding: PositionEncoding) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (index, ch) in line.char_indices() {
+        if units >= position.character {
+            return Some(line_start + index);
+        }
+        units += encoding.width(ch);
+    }
+    (units >= position.character).then_some(line_start + line.len())
+}
+
+/// Splits `text` into (before, selection, after) around `range`, keeping at
+/// most `context_lines` lines on either side.
+pub fn split_at_range(
+    text: &str,
+    range: Range,
+    context_lines: usize,
+    encoding: PositionEncoding,
+) -> Option<(String, String, String)> {
+    let start = offset(text, range.start, encoding)?;
+    let end = offset(text, range.end, encoding)?;
+    if start > end {
+        return None;
+    }
+
+    let before_lines: Vec<&str> = text[..start].split_inclusive('\n').collect();
+    let before = before_lines[before_lines.len().saturating_sub(context_lines)..].concat();
+    let after: String = text[end..]
+        .split_inclusive('\n')
+        .take(context_lines)
+        .collect();
+
+    Some((before, text[start..end].to_string(), after))
+}
+
+pub fn language_for(path: &Path) -> Option<&'static str> {
+    let language = match path.extension()?.to_str()? {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "lua" => "lua",
+        _ => return None,
+    };
+    Some(language)
+}
+
+fn build_prompt(
+    request: &ActionRequest,
+    language: &str,
+    selection: &str,
+    context: &SurroundingContext,
+) -> String {
+    format!(
+        "You are editing {file}. Rewrite the selected {language} code according to the instruction.\n\
+         Reply with only the replacement for the selection in a single fenced code block, \
+         keeping the surrounding indentation. Do not repeat the code before or after the selection.\n\n\
+         Instruction: {instruction}\n\n\
+         Code before the selection:\n```{language}\n{before}\n```\n\n\
+         Selection:\n```{language}\n{selection}\n```\n\n\
+         Code after the selection:\n```{language}\n{after}\n```\n",
+        file = request.file.display(),
+        instruction = request.instruction,
+        before = context.before,
+        after = context.after,
+    )
+}
+
+/// Contents of the first fenced code block, or the whole trimmed reply if
+/// the model didn't use one.
+pub fn extract_code(reply: &str) -> String {
+    let Some(open) = reply.find("```") else {
+        return reply.trim().to_string();
+    };
+    let after_fence = &reply[open + 3..];
+    let body_start = after_fence.find('\n').map_or(after_fence.len(), |i| i + 1);
+    let body = &after_fence[body_start..];
+    match body.find("```") {
+        Some(close) => body[..close].trim_end_matches([' ', '\t']).to_string(),
+        None => body.to_string(),
+    }
+}
+
+/// Project instructions that apply to `file`, discovered from its directory
+/// rather than the process working directory (editors may start us anywhere).
+pub fn system_prompt_for(coder: &AiCoder, file: &Path) -> Result<Option<String>, RuntimeError> {
+    match file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => coder.system_prompt_for(dir),
+        None => coder.system_prompt_for(&std::env::current_dir()?),
+    }
+}
+
+pub async fn handle(
+    coder: &AiCoder,
+    request: ActionRequest,
+) -> Result<ActionResponse, RuntimeError> {
+    if request.kind == ActionKind::Complete {
+        return handle_complete(coder, request).await;
+    }
+    if request.instruction.trim().is_empty() {
+        return Err(RuntimeError::Usage(
+            "edit requests need an instruction".to_string(),
+        ));
+    }
+
+    let (selection, context) = match (&request.selection, &request.context) {
+        (Some(selection), Some(context)) => (selection.clone(), context.clone()),
+        _ => {
+            let text = fs::read_to_string(&request.file)?;
+            let (before, selection, after) =
+                split_at_range(&text, request.range, CONTEXT_LINES, PositionEncoding::Utf32)
+                    .ok_or_else(|| {
+                        RuntimeError::Usage(format!(
+                            "range is outside of {}",
+                            request.file.display()
+                        ))
+                    })?;
+            (
+                request.selection.clone().unwrap_or(selection),
+                request
+                    .context
+                    .clone()
+                    .unwrap_or(SurroundingContext { before, after }),
+            )
+        }
+    };
+
+    let language = request
+        .language
+        .clone()
+        .or_else(|| language_for(&request.file).map(str::to_string))
+        .unwrap_or_default();
+
+    let generate = GenerateRequest {
+        model: coder.config().model.clone(),
+        prompt: build_prompt(&request, &language, &selection, &context),
+        system: system_prompt_for(coder, &request.file)?,
+        ..Default::default()
+    };
+    let completion = coder.generate(&generate).await?;
+
+    let mut new_text = extract_code(&completion.text);
+    if !selection.ends_with('\n') && new_text.ends_with('\n') {
+        new_text.pop();
+    }
+
+    Ok(ActionResponse {
+        id: request.id,
+        file: request.file,
+        range: request.range,
+        new_text,
+        model: completion.metadata.model,
+    })
+}
+
+/// Fill-in-the-middle completion at `range.start`. The returned edit
+/// inserts at the cursor.
+async fn handle_complete(
+    coder: &AiCoder,
+    request: ActionRequest,
+) -> Result<ActionResponse, RuntimeError> {
+    let cursor = Range {
+        start: request.range.start,
+        end: request.range.start,
+    };
+    let context = match request.context {
+        Some(context) => context,
+        None => {
+            let text = fs::read_to_string(&request.file)?;
+            let (before, _, after) =
+                split_at_range(&text, cursor, FIM_CONTEXT_LINES, PositionEncoding::Utf32)
+                    .ok_or_else(|| {
+                        RuntimeError::Usage(format!(
+                            "cursor is outside of {}",
+                            request.file.display()
+                        ))
+                    })?;
+            SurroundingContext { before, after }
+        }
+    };
+
+    let options = FimOptions {
+        template: request.fim_template,
+        ..Default::default()
+    };
+    let completion = coder
+        .complete_fim(&context.before, &context.after, &options)
+        .await?;
+
+    Ok(ActionResponse {
+        id: request.id,
+        file: request.file,
+        range: cursor,
+        new_text: completion.text,
+        model: completion.metadata.model,
+    })
+}
+
+/// Reads one JSON request per line from stdin and writes one JSON response
+/// per line to stdout until EOF. Failures are reported per request as
+/// `{"id", "error", "hint"}` so a long-lived editor session keeps running.
+pub async fn run_stdio(coder: &AiCoder) -> Result<(), RuntimeError> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<ActionRequest>(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                match coder.edit(request).await {
+                    Ok(response) => serde_json::to_value(response)?,
+                    Err(err) => error_reply(id, &err),
+                }
+            }
+            Err(err) => error_reply(
+                None,
+                &RuntimeError::Usage(format!("invalid request: {err}")),
+            ),
+        };
+
+        writeln!(stdout, "{}", reply)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn error_reply(id: Option<serde_json::Value>, err: &RuntimeError) -> serde_json::Value {
+    json!({
+        "id": id,
+        "error": err.to_string(),
+        "hint": err.user_hint(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_code, split_at_range, Position, PositionEncoding, Range};
+
+    fn range(start: (usize, usize), end: (usize, usize)) -> Range {
+        Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn splits_text_around_a_multiline_range() {
+        let text = "fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+
+        let (before, selection, after) =
+            split_at_range(text, range((1, 4), (2, 20)), 30, PositionEncoding::Utf32).unwrap();
+
+        assert_eq!(before, "fn main() {\n    ");
+        assert_eq!(selection, "let x = 1;\n    println!(\"{x}\");");
+        assert_eq!(after, "\n}\n");
+        assert!(split_at_range(text, range((9, 0), (9, 0)), 30, PositionEncoding::Utf32).is_none());
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        let text = "let s = \"héllo\";\n";
+
+        let (_, selection, _) =
+            split_at_range(text, range((0, 9), (0, 14)), 30, PositionEncoding::Utf32).unwrap();
+
+        assert_eq!(selection, "héllo");
+    }
+
+    #[test]
+    fn counts_utf16_code_units_for_lsp() {
+        let text = "let s = \"🦀 crab\";\n";
+
+        let (_, selection, _) =
+            split_at_range(text, range((0, 9), (0, 16)), 30, PositionEncoding::Utf16).unwrap();
+
+        assert_eq!(selection, "🦀 crab");
+        assert!(
+            split_at_range(text, range((0, 20), (0, 20)), 30, PositionEncoding::Utf16).is_none()
+        );
+    }
+
+    #[test]
+    fn extracts_the_first_fenced_block() {
+        let reply = "Here you go:\n```rust\nlet y = x + 1;\n```\nExplanation follows.";
+
+        assert_eq!(extract_code(reply), "let y = x + 1;\n");
+        assert_eq!(extract_code("  let y = 2;  "), "let y = 2;");
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a server that failed with a connection or server error is
+/// avoided.
+const EJECT_FOR: Duration = Duration::from_secs(30);
+
+/// Spreads requests over several servers running the same model: each
+/// request goes to the server with the fewest requests in flight, skipping
+/// servers that failed recently.
+#[derive(Debug)]
+pub struct Balancer {
+    hosts: Vec<String>,
+    state: Mutex<Vec<Endpoint>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Endpoint {
+    pending: usize,
+    ejected_until: Option<Instant>,
+}
+
+/// A request in flight on one server; released on drop.
+pub struct Lease<'a> {
+    balancer: &'a Balancer,
+    pub index: usize,
+}
+
+impl Balancer {
+    pub fn new(hosts: Vec<String>) -> Self {
+        Balancer {
+            state: Mutex::new(vec![Endpoint::default(); hosts.len()]),
+            hosts,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// The least busy server not in `exclude`. Ejected servers are only
+    /// used once every healthy one has been excluded.
+    pub fn acquire(&self, exclude: &[usize]) -> Option<Lease<'_>> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        let index = (0..self.hosts.len())
+            .filter(|index| !exclude.contains(index))
+            .min_by_key(|&index| {
+                let endpoint = &state[index];
+                let ejected = endpoint.ejected_until.is_some_and(|until| until > now);
+                (ejected, endpoint.pending)
+            })?;
+        state[index].pending += 1;
+        Some(Lease {
+            balancer: self,
+            index,
+        })
+    }
+
+    pub fn eject(&self, index: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state[index].ejected_until = Some(Instant::now() + EJECT_FOR);
+    }
+}
+
+impl Lease<'_> {
+    pub fn host(&self) -> &str {
+        &self.balancer.hosts[self.index]
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut state = self
+            .balancer
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        state[self.index].pending -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Balancer;
+
+    fn balancer() -> Balancer {
+        Balancer::new(vec!["http://gpu0".to_string(), "http://gpu1".to_string()])
+    }
+
+    #[test]
+    fn requests_go_to_the_least_busy_server() {
+        let balancer = balancer();
+
+        let first = balancer.acquire(&[]).unwrap();
+        let second = balancer.acquire(&[]).unwrap();
+        assert_eq!(
+            (first.host(), second.host()),
+            ("http://gpu0", "http://gpu1")
+        );
+
+        drop(second);
+        assert_eq!(balancer.acquire(&[]).unwrap().host(), "http://gpu1");
+    }
+
+    #[test]
+    fn ejected_servers_are_used_only_as_a_last_resort() {
+        let balancer = balancer();
+        balancer.eject(0);
+
+        let lease = balancer.acquire(&[]).unwrap();
+        assert_eq!(lease.host(), "http://gpu1");
+        assert_eq!(balancer.acquire(&[1]).unwrap().host(), "http://gpu0");
+        assert!(balancer.acquire(&[0, 1]).is_none());
+    }
+}
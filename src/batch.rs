@@ -0,0 +1,362 @@
+use crate::action;
+use crate::error::RuntimeError;
+use crate::ollama::GenerateRequest;
+use crate::prompts;
+use crate::syntax::{self, Checked};
+use crate::AiCoder;
+use futures_util::stream::{self, StreamExt};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Variables available to batch prompt templates.
+const BATCH_VARIABLES: [&str; 3] = ["file", "path", "language"];
+
+/// How long a file waits for a restarted server before giving up.
+const RESTART_WAIT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Instruction applied to every file. May use `{{file}}` (contents),
+    /// `{{path}}`, and `{{language}}`; without `{{file}}` the contents are
+    /// appended as a fenced block.
+    pub template: String,
+    pub pattern: String,
+    pub output_dir: PathBuf,
+    /// Requests in flight at once.
+    pub jobs: usize,
+    /// Write the first code block of each reply to `<output_dir>/<path>`
+    /// instead of the whole reply to `<output_dir>/<path>.md`.
+    pub extract_code: bool,
+    /// With `extract_code`, fail a file instead of writing it when the
+    /// rewrite drops more than this percentage of the original's lines,
+    /// which usually means the model truncated it.
+    pub max_deleted_percent: u8,
+    /// With `extract_code`, run each rewrite through its language's
+    /// formatter (rustfmt, prettier, black, gofmt) and write the formatted
+    /// result; rewrites that don't parse are retried once.
+    pub check_syntax: bool,
+}
+
+/// Files matching `pattern`, sorted.
+pub fn matching_files(pattern: &str) -> Result<Vec<PathBuf>, RuntimeError> {
+    let paths = glob::glob(pattern)
+        .map_err(|err| RuntimeError::Usage(format!("invalid glob `{pattern}`: {err}")))?;
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.map_err(|err| RuntimeError::Io(err.into()))?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the result for `file` is written. The file's relative path is
+/// mirrored under `output_dir`. `..` becomes a `__parent__` segment and a
+/// leading `/` a `__root__` one, so results never land outside it and
+/// `../shared/lib.rs` doesn't collide with `shared/lib.rs`.
+pub fn output_path(output_dir: &Path, file: &Path, extract_code: bool) -> PathBuf {
+    let mut path = output_dir.to_path_buf();
+    for component in file.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => path.push("__parent__"),
+            Component::RootDir => path.push("__root__"),
+            Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    if !extract_code {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".md");
+        path.set_file_name(name);
+    }
+    path
+}
+
+/// Percentage of `original`'s non-blank lines missing from `rewritten`,
+/// ignoring indentation. Moved lines count as kept.
+fn deleted_percent(original: &str, rewritten: &str) -> u8 {
+    let mut remaining: BTreeMap<&str, usize> = BTreeMap::new();
+    for line in rewritten
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        *remaining.entry(line).or_default() += 1;
+    }
+    let (mut total, mut deleted) = (0, 0);
+    for line in original
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        total += 1;
+        match remaining.get_mut(line) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => deleted += 1,
+        }
+    }
+    if total == 0 {
+        return 0;
+    }
+    (deleted * 100 / total) as u8
+}
+
+fn check_template(template: &str) -> Result<(), RuntimeError> {
+    let unknown: Vec<String> = prompts::variables(template)
+        .into_iter()
+        .filter(|name| !BATCH_VARIABLES.contains(&name.as_str()))
+        .map(|name| format!("{{{{{name}}}}}"))
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(RuntimeError::Usage(format!(
+            "batch prompts only support {{{{file}}}}, {{{{path}}}} and {{{{language}}}}; found {}",
+            unknown.join(", ")
+        )))
+    }
+}
+
+fn build_prompt(template: &str, file: &Path, contents: &str) -> Result<String, RuntimeError> {
+    let language = action::language_for(file).unwrap_or_default();
+    let vars = BTreeMap::from([
+        ("file".to_string(), contents.to_string()),
+        ("path".to_string(), file.display().to_string()),
+        ("language".to_string(), language.to_string()),
+    ]);
+    let mut prompt = prompts::render(template, &vars)?;
+    if !prompts::variables(template)
+        .iter()
+        .any(|name| name == "file")
+    {
+        prompt.truncate(prompt.trim_end().len());
+        prompt.push_str(&format!(
+            "\n\nFile: {}\n```{language}\n{}\n```\n",
+            file.display(),
+            contents.trim_end()
+        ));
+    }
+    Ok(prompt)
+}
+
+/// `coder.generate`, waiting once for a server that went away.
+async fn generate(
+    coder: &AiCoder,
+    request: &GenerateRequest,
+    file: &Path,
+) -> Result<String, RuntimeError> {
+    let completion = match coder.generate(request).await {
+        // Ollama restarted (e.g. to free VRAM): wait for it instead of
+        // failing every remaining file.
+        Err(RuntimeError::Connect(_) | RuntimeError::Transport(_)) => {
+            eprintln!(
+                "[ai-coder] {}: lost the connection to the server; waiting up to {}s for it",
+                file.display(),
+                RESTART_WAIT.as_secs()
+            );
+            if !coder.wait_until_ready(RESTART_WAIT).await {
+                eprintln!(
+                    "[ai-coder] {}: the server did not come back",
+                    file.display()
+                );
+            }
+            coder.generate(request).await?
+        }
+        result => result?,
+    };
+    Ok(completion.text)
+}
+
+/// `code` as formatted by its language's formatter. Code that doesn't
+/// parse is sent back to the model with the error once before giving up.
+async fn check_syntax(
+    coder: &AiCoder,
+    request: &GenerateRequest,
+    file: &Path,
+    code: String,
+) -> Result<String, RuntimeError> {
+    let error = match syntax::check(file, &code).await? {
+        Checked::Formatted(formatted) => return Ok(formatted),
+        Checked::Unchecked => return Ok(code),
+        Checked::Invalid(error) => error,
+    };
+    eprintln!(
+        "[ai-coder] {}: the rewrite does not parse; asking for a fix",
+        file.display()
+    );
+    let language = action::language_for(file).unwrap_or_default();
+    let retry = GenerateRequest {
+        prompt: format!(
+            "{}\n\nYour previous reply was:\n```{language}\n{}\n```\n\n\
+             It does not parse:\n{error}\n\n\
+             Reply with the corrected file in a single fenced code block.\n",
+            request.prompt.trim_end(),
+            code.trim_end()
+        ),
+        ..request.clone()
+    };
+    let code = action::extract_code(&generate(coder, &retry, file).await?);
+    match syntax::check(file, &code).await? {
+        Checked::Formatted(formatted) => Ok(formatted),
+        Checked::Unchecked => Ok(code),
+        Checked::Invalid(error) => Err(RuntimeError::Usage(format!(
+            "the rewrite of {} does not parse, even after a retry:\n{error}",
+            file.display()
+        ))),
+    }
+}
+
+async fn process(
+    coder: &AiCoder,
+    options: &BatchOptions,
+    file: &Path,
+) -> Result<PathBuf, RuntimeError> {
+    let contents = fs::read_to_string(file)?;
+    let request = GenerateRequest {
+        model: coder.config().model.clone(),
+        prompt: build_prompt(&options.template, file, &contents)?,
+        system: action::system_prompt_for(coder, file)?,
+        ..Default::default()
+    };
+    let reply = generate(coder, &request, file).await?;
+
+    let result = if options.extract_code {
+        let mut code = action::extract_code(&reply);
+        if options.check_syntax {
+            code = check_syntax(coder, &request, file, code).await?;
+        }
+        let deleted = deleted_percent(&contents, &code);
+        if deleted > options.max_deleted_percent {
+            return Err(RuntimeError::Usage(format!(
+                "the rewrite of {} drops {deleted}% of its lines, more than --max-deleted {}; \
+                 not writing it (pass --max-deleted 100 to accept it)",
+                file.display(),
+                options.max_deleted_percent
+            )));
+        }
+        code
+    } else {
+        reply
+    };
+    let destination = output_path(&options.output_dir, file, options.extract_code);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&destination, result)?;
+    Ok(destination)
+}
+
+/// Runs the template over every file matching `options.pattern`, at most
+/// `options.jobs` at a time. `on_done` is called as each file finishes; a
+/// failed file doesn't stop the others, but makes the batch fail overall.
+pub async fn run<F>(
+    coder: &AiCoder,
+    options: &BatchOptions,
+    mut on_done: F,
+) -> Result<(), RuntimeError>
+where
+    F: FnMut(&Path, Result<PathBuf, RuntimeError>),
+{
+    check_template(&options.template)?;
+    let files = matching_files(&options.pattern)?;
+    if files.is_empty() {
+        return Err(RuntimeError::Usage(format!(
+            "no files match `{}`",
+            options.pattern
+        )));
+    }
+
+    let total = files.len();
+    let mut failed = 0;
+    let mut results = stream::iter(&files)
+        .map(|file| async move { (file, process(coder, options, file).await) })
+        .buffer_unordered(options.jobs.max(1));
+    while let Some((file, result)) = results.next().await {
+        if result.is_err() {
+            failed += 1;
+        }
+        on_done(file, result);
+    }
+
+    if failed > 0 {
+        return Err(RuntimeError::BatchFailed { failed, total });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_prompt, check_template, deleted_percent, output_path};
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    #[test]
+    fn output_paths_mirror_the_input_tree_inside_the_output_dir() {
+        let out = Path::new("out");
+
+        assert_eq!(
+            output_path(out, Path::new("./src/main.rs"), false),
+            Path::new("out/src/main.rs.md")
+        );
+        assert_eq!(
+            output_path(out, Path::new("../shared/lib.rs"), true),
+            Path::new("out/__parent__/shared/lib.rs")
+        );
+        assert_eq!(
+            output_path(out, Path::new("/srv/lib.rs"), true),
+            Path::new("out/__root__/srv/lib.rs")
+        );
+    }
+
+    #[test]
+    fn files_outside_the_current_directory_do_not_collide() {
+        let out = Path::new("out");
+        let files = ["shared/lib.rs", "../shared/lib.rs", "../../shared/lib.rs"];
+
+        let paths: BTreeSet<_> = files
+            .iter()
+            .map(|file| output_path(out, Path::new(file), false))
+            .collect();
+        assert_eq!(paths.len(), files.len());
+        assert!(paths.iter().all(|path| path.starts_with(out)));
+    }
+
+    #[test]
+    fn contents_are_appended_unless_the_template_places_them() {
+        let file = Path::new("src/lib.rs");
+
+        let appended = build_prompt("Add doc comments\n", file, "fn a() {}\n").unwrap();
+        assert_eq!(
+            appended,
+            "Add doc comments\n\nFile: src/lib.rs\n```rust\nfn a() {}\n```\n"
+        );
+
+        let placed = build_prompt("Review {{path}}:\n{{file}}", file, "fn a() {}").unwrap();
+        assert_eq!(placed, "Review src/lib.rs:\nfn a() {}");
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected_before_any_request() {
+        let err = check_template("Port {{file}} to {{lang}}").unwrap_err();
+
+        assert!(err.to_string().contains("{{lang}}"));
+        assert!(check_template("Document {{path}}").is_ok());
+    }
+
+    #[test]
+    fn deleted_lines_are_measured_against_the_original() {
+        let original = "fn a() {}\n\nfn b() {}\nfn c() {}\nfn d() {}\n";
+
+        assert_eq!(deleted_percent(original, original), 0);
+        assert_eq!(
+            deleted_percent(original, "fn d() {}\n    fn a() {}\nfn b() {}\nfn c() {}"),
+            0
+        );
+        assert_eq!(deleted_percent(original, "fn a() {}\n// ...\n"), 75);
+        assert_eq!(deleted_percent("", "fn a() {}"), 0);
+    }
+}
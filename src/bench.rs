@@ -0,0 +1,245 @@
+use crate::config;
+use crate::error::RuntimeError;
+use crate::init;
+use crate::ollama::{self, GenerateRequest, GenerationOptions};
+use reqwest::Client;
+use std::path::Path;
+
+const SPEED_PROMPT: &str = "Write a Rust function that reverses a string.";
+
+/// Short questions with one right answer, as `(prompt, expected)`. An
+/// answer passes if it contains `expected`, ignoring case.
+const PROBES: [(&str, &str); 5] = [
+    ("What is 17 * 23? Reply with only the number.", "391"),
+    (
+        "What does the Rust expression `(1..=4).sum::<i32>()` evaluate to? Reply with only the number.",
+        "10",
+    ),
+    (
+        "Complete this Python slice so that `xs[...]` is `xs` reversed. Reply with only the slice, including the brackets.",
+        "[::-1]",
+    ),
+    (
+        "Which Rust standard library type is a growable, heap-allocated array? Reply with only the type name.",
+        "vec",
+    ),
+    (
+        "What does `git rev-parse --abbrev-ref HEAD` print? Reply in at most five words.",
+        "branch",
+    ),
+];
+
+/// How a model did on this machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantResult {
+    pub model: String,
+    /// Generation speed, excluding load and prompt time; `None` if the
+    /// server didn't report timings.
+    pub tokens_per_second: Option<f64>,
+    pub passed: usize,
+    pub total: usize,
+}
+
+/// The installed variants of `family` (e.g. `qwen2.5-coder` or
+/// `qwen2.5-coder:7b`): names with its base and, if given, its tag prefix.
+pub fn installed_variants(family: &str, installed: &[String]) -> Vec<String> {
+    let (base, tag) = family.split_once(':').unwrap_or((family, ""));
+    installed
+        .iter()
+        .filter(|name| {
+            let (name_base, name_tag) = name.split_once(':').unwrap_or((name, "latest"));
+            name_base == base && name_tag.starts_with(tag)
+        })
+        .cloned()
+        .collect()
+}
+
+/// The variant to recommend: the most probes passed, then the fastest.
+pub fn best(results: &[VariantResult]) -> Option<&VariantResult> {
+    results.iter().max_by(|a, b| {
+        a.passed.cmp(&b.passed).then(
+            a.tokens_per_second
+                .unwrap_or(0.0)
+                .total_cmp(&b.tokens_per_second.unwrap_or(0.0)),
+        )
+    })
+}
+
+fn passes(answer: &str, expected: &str) -> bool {
+    answer.to_lowercase().contains(&expected.to_lowercase())
+}
+
+/// Tokens per second for a short generation on `host`.
+pub async fn measure_speed(
+    client: &Client,
+    host: &str,
+    model: &str,
+) -> Result<Option<f64>, RuntimeError> {
+    let request = GenerateRequest {
+        model: model.to_string(),
+        prompt: SPEED_PROMPT.to_string(),
+        options: Some(GenerationOptions {
+            num_predict: Some(64),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let completion = ollama::complete(client, host, &request).await?;
+    Ok(completion.metadata.tokens_per_second())
+}
+
+/// Measures `model`'s speed and runs the probe set with greedy sampling.
+pub async fn bench(
+    client: &Client,
+    host: &str,
+    model: &str,
+) -> Result<VariantResult, RuntimeError> {
+    // Measured first so loading the model isn't counted against a probe.
+    let tokens_per_second = measure_speed(client, host, model).await?;
+    let mut passed = 0;
+    for (prompt, expected) in PROBES {
+        let request = GenerateRequest {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            options: Some(GenerationOptions {
+                temperature: Some(0.0),
+                num_predict: Some(32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let completion = ollama::complete(client, host, &request).await?;
+        if passes(&completion.text, expected) {
+            passed += 1;
+        }
+    }
+    Ok(VariantResult {
+        model: model.to_string(),
+        tokens_per_second,
+        passed,
+        total: PROBES.len(),
+    })
+}
+
+/// Pulls the `pull` tags of `family` that are missing, benchmarks every
+/// installed variant and prints one result per line. With `save`, the best
+/// one becomes `profile`'s model (or the default model) in `config_path`.
+pub async fn run_variants(
+    client: &Client,
+    host: &str,
+    family: &str,
+    pull: &[String],
+    config_path: &Path,
+    profile: Option<&str>,
+    save: bool,
+) -> Result<(), RuntimeError> {
+    let base = family.split(':').next().unwrap_or(family);
+    let mut installed = ollama::list_models(client, host).await?;
+    for tag in pull {
+        let model = format!("{base}:{tag}");
+        if !installed.contains(&model) {
+            eprintln!("[ai-coder] Pulling {model}");
+            init::pull(client, host, &model).await?;
+            installed.push(model);
+        }
+    }
+    let variants = installed_variants(family, &installed);
+    if variants.is_empty() {
+        return Err(RuntimeError::Usage(format!(
+            "no variants of {family} are installed on {host}; pull some with --pull"
+        )));
+    }
+
+    let mut results = Vec::new();
+    for model in variants {
+        eprintln!("[ai-coder] Benchmarking {model}...");
+        match bench(client, host, &model).await {
+            Ok(result) => {
+                let speed = result.tokens_per_second.map_or_else(
+                    || "? tok/s".to_string(),
+                    |speed| format!("{speed:.1} tok/s"),
+                );
+                println!(
+                    "{model}\t{speed}\t{}/{} probes",
+                    result.passed, result.total
+                );
+                results.push(result);
+            }
+            // One variant too big for this machine shouldn't stop the rest.
+            Err(err) => {
+                println!("{model}\tfailed");
+                eprintln!("[ai-coder] warning: {model} failed: {err}");
+                if let Some(hint) = err.user_hint() {
+                    eprintln!("[ai-coder] hint: {hint}");
+                }
+            }
+        }
+    }
+    let Some(best) = best(&results) else {
+        return Err(RuntimeError::Usage(format!(
+            "every variant of {family} failed"
+        )));
+    };
+    eprintln!("[ai-coder] Best on this machine: {}", best.model);
+    if save {
+        config::save_model(config_path, profile, &best.model)?;
+        let target = profile.map_or_else(
+            || "the default model".to_string(),
+            |name| format!("profile {name}'s model"),
+        );
+        eprintln!(
+            "[ai-coder] Saved {} as {target} in {}",
+            best.model,
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{best, installed_variants, passes, VariantResult};
+
+    #[test]
+    fn variants_match_the_family_and_tag_prefix() {
+        let installed = [
+            "qwen2.5-coder:7b".to_string(),
+            "qwen2.5-coder:7b-instruct-q8_0".to_string(),
+            "qwen2.5-coder:1.5b".to_string(),
+            "qwen2.5-coder-extra:7b".to_string(),
+            "qwen2.5-coder".to_string(),
+        ];
+
+        assert_eq!(
+            installed_variants("qwen2.5-coder:7b", &installed),
+            vec!["qwen2.5-coder:7b", "qwen2.5-coder:7b-instruct-q8_0"]
+        );
+        assert_eq!(installed_variants("qwen2.5-coder", &installed).len(), 4);
+    }
+
+    #[test]
+    fn best_prefers_quality_then_speed() {
+        let result = |model: &str, speed, passed| VariantResult {
+            model: model.to_string(),
+            tokens_per_second: speed,
+            passed,
+            total: 5,
+        };
+        let results = [
+            result("q4", Some(60.0), 4),
+            result("q8", Some(35.0), 5),
+            result("fp16", Some(12.0), 5),
+            result("q2", None, 2),
+        ];
+
+        assert_eq!(best(&results).unwrap().model, "q8");
+        assert_eq!(best(&[]), None);
+    }
+
+    #[test]
+    fn answers_pass_when_they_contain_the_expected_text() {
+        assert!(passes("The answer is **391**.", "391"));
+        assert!(passes("`Vec<T>`", "vec"));
+        assert!(!passes("392", "391"));
+    }
+}
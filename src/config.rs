@@ -0,0 +1,656 @@
+use crate::error::RuntimeError;
+use crate::http::HttpConfig;
+use crate::prompts::PromptTemplate;
+use crate::transform::Transform;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+pub const DEFAULT_CONFIG_PATH: &str = ".ai-coder.toml";
+
+/// `$XDG_CONFIG_HOME/ai-coder` (falling back to `~/.config/ai-coder`), for
+/// user-wide settings and instructions.
+pub fn user_config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("ai-coder"))
+}
+
+/// The user-wide config file, `config.toml` in [`user_config_dir`].
+pub fn user_config_path() -> Option<PathBuf> {
+    user_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// The config file to use without `--config`: `local` (`.ai-coder.toml`
+/// in the current directory) if it exists, else the user-wide file if
+/// that exists, so its directory-mapped profiles apply anywhere.
+pub fn config_path(local: &Path, user: Option<&Path>) -> PathBuf {
+    match user {
+        Some(user) if !local.exists() && user.exists() => user.to_path_buf(),
+        _ => local.to_path_buf(),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct FileConfig {
+    pub model: Option<String>,
+    pub host: Option<String>,
+    /// More servers with the same models, for spreading batch work.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Natural language for answers, e.g. "German".
+    pub language: Option<String>,
+    #[serde(default)]
+    pub prompts: BTreeMap<String, PromptTemplate>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Per-model settings, keyed by model name prefix (`deepseek-r1`
+    /// matches `deepseek-r1:14b`).
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelSettings>,
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Reach `host` through an SSH tunnel to this destination
+    /// (`user@gpubox`); `host` is then resolved on that machine.
+    pub ssh: Option<String>,
+}
+
+/// A `[models.<name>]` section.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModelSettings {
+    pub transforms: Option<Vec<Transform>>,
+    /// Ollama Modelfile that `models create-from-modelfile` builds this
+    /// model from, relative to the config file.
+    pub modelfile: Option<PathBuf>,
+    /// Model the `adapters` are applied to, when there is no `modelfile`.
+    pub base: Option<String>,
+    /// LoRA adapters in GGUF form (from Ollama or llama.cpp's
+    /// `convert_lora_to_gguf.py`), applied on top of `base`. Relative to
+    /// the config file.
+    #[serde(default)]
+    pub adapters: Vec<PathBuf>,
+    /// Context window to request, in tokens. `models probe-context` writes
+    /// the largest one the model was verified to use.
+    pub num_ctx: Option<u32>,
+}
+
+/// A `[profiles.<name>]` section. Set fields override the top level of the
+/// file (and of the `inherits` profile); unset fields fall through.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub model: Option<String>,
+    pub host: Option<String>,
+    pub hosts: Option<Vec<String>>,
+    pub language: Option<String>,
+    /// Replaces the top-level `[http]` section as a whole.
+    pub http: Option<HttpConfig>,
+    pub ssh: Option<String>,
+    /// Another profile whose settings this one starts from.
+    pub inherits: Option<String>,
+    /// Select this profile automatically when running inside any of these
+    /// directories (`~/` is expanded).
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+}
+
+impl FileConfig {
+    /// Settings for `model` from the most specific matching `[models.*]` key.
+    pub fn model_settings(&self, model: &str) -> Option<&ModelSettings> {
+        model_settings(&self.models, model)
+    }
+
+    /// The profile to use: `explicit` if given, otherwise the profile whose
+    /// `directories` most specifically contain `cwd`.
+    pub fn select_profile(&self, explicit: Option<&str>, cwd: &Path) -> Option<String> {
+        if let Some(name) = explicit {
+            return Some(name.to_string());
+        }
+        self.profiles
+            .iter()
+            .flat_map(|(name, profile)| {
+                profile
+                    .directories
+                    .iter()
+                    .map(|dir| expand_home(dir))
+                    .filter(|dir| cwd.starts_with(dir))
+                    .map(move |dir| (dir.components().count(), name))
+            })
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, name)| name.clone())
+    }
+
+    /// Overlays profile `name` (after its `inherits` chain) onto the
+    /// top-level settings.
+    pub fn apply_profile(mut self, name: &str) -> Result<FileConfig, String> {
+        let mut chain = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(current) = next {
+            if chain.contains(&current) {
+                return Err(format!(
+                    "profile '{name}' inherits from itself via '{current}'"
+                ));
+            }
+            let profile = self
+                .profiles
+                .get(&current)
+                .ok_or_else(|| format!("no profile named '{current}'"))?;
+            next = profile.inherits.clone();
+            chain.push(current);
+        }
+
+        for current in chain.iter().rev() {
+            let profile = &self.profiles[current];
+            if profile.model.is_some() {
+                self.model = profile.model.clone();
+            }
+            if profile.host.is_some() {
+                self.host = profile.host.clone();
+            }
+            if let Some(hosts) = &profile.hosts {
+                self.hosts = hosts.clone();
+            }
+            if profile.language.is_some() {
+                self.language = profile.language.clone();
+            }
+            if profile.ssh.is_some() {
+                self.ssh = profile.ssh.clone();
+            }
+            if let Some(http) = &profile.http {
+                self.http = http.clone();
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Name prefixes of reasoning models that write their thinking inline, in
+/// a `<think>` block at the start of the response.
+const REASONING_MODELS: [&str; 7] = [
+    "deepseek-r1",
+    "qwq",
+    "qwen3",
+    "phi4-reasoning",
+    "exaone-deep",
+    "openthinker",
+    "cogito",
+];
+
+/// Transforms for a model without configured ones: `strip-thinking` for
+/// known reasoning models, nothing otherwise.
+pub fn default_transforms(model: &str) -> Vec<Transform> {
+    // Ignore a registry or namespace, as in `hf.co/unsloth/qwq`.
+    let name = model.rsplit('/').next().unwrap_or(model);
+    if REASONING_MODELS
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        vec![Transform::StripThinking]
+    } else {
+        Vec::new()
+    }
+}
+
+/// The entry in `models` whose key is the longest prefix of `model`.
+pub fn model_settings<'a>(
+    models: &'a BTreeMap<String, ModelSettings>,
+    model: &str,
+) -> Option<&'a ModelSettings> {
+    models
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, settings)| settings)
+}
+
+/// Sets the top-level `model` and `host` in the config file, creating it if
+/// needed and keeping everything else (profiles, prompts, comments).
+pub fn save_defaults(path: &Path, model: &str, host: &str) -> Result<(), RuntimeError> {
+    let content = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+    let mut doc: DocumentMut = content.parse().map_err(|err| RuntimeError::Config {
+        path: path.to_path_buf(),
+        source: Box::new(err),
+    })?;
+    doc["model"] = value(model);
+    doc["host"] = value(host);
+    fs::write(path, doc.to_string())?;
+    Ok(())
+}
+
+/// Sets `model` in profile `profile`, or at the top level of the config
+/// file without one, keeping everything else.
+pub fn save_model(path: &Path, profile: Option<&str>, model: &str) -> Result<(), RuntimeError> {
+    let content = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+    let mut doc: DocumentMut = content.parse().map_err(|err| RuntimeError::Config {
+        path: path.to_path_buf(),
+        source: Box::new(err),
+    })?;
+    match profile {
+        Some(name) => doc["profiles"][name]["model"] = value(model),
+        None => doc["model"] = value(model),
+    }
+    fs::write(path, doc.to_string())?;
+    Ok(())
+}
+
+/// Sets `num_ctx` in the `[models.<model>]` section, creating it if
+/// needed and keeping everything else.
+pub fn save_num_ctx(path: &Path, model: &str, num_ctx: u32) -> Result<(), RuntimeError> {
+    let config_error = |source: Box<dyn Error + Send + Sync>| RuntimeError::Config {
+        path: path.to_path_buf(),
+        source,
+    };
+    let content = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+    let mut doc: DocumentMut = content.parse().map_err(|err| config_error(Box::new(err)))?;
+    let models = doc
+        .entry("models")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .ok_or_else(|| config_error("`models` must be a table".into()))?;
+    models
+        .entry(model)
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_like_mut()
+        .ok_or_else(|| config_error(format!("`models.{model}` must be a table").into()))?
+        .insert("num_ctx", value(i64::from(num_ctx)));
+    fs::write(path, doc.to_string())?;
+    Ok(())
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveConfig {
+    pub model: String,
+    pub host: String,
+    /// `host` followed by the other configured servers, unless the host
+    /// was set on the command line or in the environment.
+    pub hosts: Vec<String>,
+    /// Output transforms for `model`; see [`default_transforms`] when
+    /// none are configured.
+    pub transforms: Vec<Transform>,
+    pub http: HttpConfig,
+    /// SSH destination to tunnel `host` through.
+    pub ssh: Option<String>,
+    /// Sampling seed sent with every request, for reproducible output.
+    pub seed: Option<u64>,
+    /// Context window sent with every request, from `model`'s settings.
+    pub num_ctx: Option<u32>,
+}
+
+pub fn load_file_config(path: &Path) -> Result<FileConfig, RuntimeError> {
+    let config_error = |source: Box<dyn Error + Send + Sync>| RuntimeError::Config {
+        path: path.to_path_buf(),
+        source,
+    };
+    let content = fs::read_to_string(path).map_err(|err| config_error(err.into()))?;
+    let config: FileConfig = toml::from_str(&content).map_err(|err| config_error(err.into()))?;
+    Ok(config)
+}
+
+/// Loads the config file if it exists; a missing file is not an error.
+pub fn load_optional(path: &Path) -> Result<Option<FileConfig>, RuntimeError> {
+    if path.exists() {
+        load_file_config(path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Loads the config file with the selected profile (explicit, or mapped from
+/// `cwd`) applied. Returns the name of the profile in use, if any.
+pub fn load_with_profile(
+    path: &Path,
+    explicit: Option<&str>,
+    cwd: &Path,
+) -> Result<(Option<FileConfig>, Option<String>), RuntimeError> {
+    let Some(file) = load_optional(path)? else {
+        return match explicit {
+            Some(name) => Err(RuntimeError::Usage(format!(
+                "--config-profile {name} needs a config file, but {} does not exist",
+                path.display()
+            ))),
+            None => Ok((None, None)),
+        };
+    };
+    let Some(name) = file.select_profile(explicit, cwd) else {
+        return Ok((Some(file), None));
+    };
+    let file = file
+        .apply_profile(&name)
+        .map_err(|message| RuntimeError::Config {
+            path: path.to_path_buf(),
+            source: message.into(),
+        })?;
+    Ok((Some(file), Some(name)))
+}
+
+pub fn resolve_config(
+    args_model: Option<String>,
+    args_host: Option<String>,
+    env_host: Option<String>,
+    file_config: Option<FileConfig>,
+) -> EffectiveConfig {
+    let file_model = file_config.as_ref().and_then(|config| config.model.clone());
+
+    let model = args_model
+        .or(file_model)
+        .unwrap_or_else(|| "qwen2.5-coder".to_string());
+
+    let settings = file_config
+        .as_ref()
+        .and_then(|config| config.model_settings(&model));
+    let transforms = settings
+        .and_then(|settings| settings.transforms.clone())
+        .unwrap_or_else(|| default_transforms(&model));
+    let num_ctx = settings.and_then(|settings| settings.num_ctx);
+    let http = file_config
+        .as_ref()
+        .map(|config| config.http.clone())
+        .unwrap_or_default();
+    let ssh = file_config.as_ref().and_then(|config| config.ssh.clone());
+    let (file_host, file_hosts) = file_config
+        .map(|config| (config.host, config.hosts))
+        .unwrap_or_default();
+
+    let overridden = args_host.is_some() || env_host.is_some();
+    let host = args_host
+        .or(env_host)
+        .or(file_host)
+        .or_else(|| file_hosts.first().cloned())
+        .unwrap_or_else(|| "http://localhost:11434".to_string());
+    let mut hosts = vec![host.clone()];
+    if !overridden {
+        for other in file_hosts {
+            if !hosts.contains(&other) {
+                hosts.push(other);
+            }
+        }
+    }
+
+    EffectiveConfig {
+        model,
+        host,
+        hosts,
+        transforms,
+        http,
+        ssh,
+        seed: None,
+        num_ctx,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        config_path, load_file_config, load_with_profile, resolve_config, save_defaults,
+        save_model, save_num_ctx, FileConfig,
+    };
+    use crate::transform::Transform;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn cli_overrides_everything() {
+        let resolved = resolve_config(
+            Some("cli-model".to_string()),
+            Some("http://cli-host:11434".to_string()),
+            Some("http://env-host:11434".to_string()),
+            Some(FileConfig {
+                model: Some("file-model".to_string()),
+                host: Some("http://file-host:11434".to_string()),
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(resolved.model, "cli-model");
+        assert_eq!(resolved.host, "http://cli-host:11434");
+    }
+
+    #[test]
+    fn env_host_overrides_file_host() {
+        let resolved = resolve_config(
+            None,
+            None,
+            Some("http://env-host:11434".to_string()),
+            Some(FileConfig {
+                model: Some("file-model".to_string()),
+                host: Some("http://file-host:11434".to_string()),
+                hosts: vec!["http://gpu1:11434".to_string()],
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(resolved.model, "file-model");
+        assert_eq!(resolved.host, "http://env-host:11434");
+        assert_eq!(resolved.hosts, vec!["http://env-host:11434"]);
+    }
+
+    #[test]
+    fn file_hosts_follow_the_primary_host() {
+        let file = |host: Option<&str>| FileConfig {
+            host: host.map(str::to_string),
+            hosts: vec![
+                "http://gpu0:11434".to_string(),
+                "http://gpu1:11434".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_config(None, None, None, Some(file(Some("http://gpu1:11434"))));
+        assert_eq!(
+            resolved.hosts,
+            vec!["http://gpu1:11434", "http://gpu0:11434"]
+        );
+
+        let resolved = resolve_config(None, None, None, Some(file(None)));
+        assert_eq!(resolved.host, "http://gpu0:11434");
+        assert_eq!(resolved.hosts.len(), 2);
+    }
+
+    #[test]
+    fn falls_back_to_defaults_without_overrides() {
+        let resolved = resolve_config(None, None, None, None);
+
+        assert_eq!(resolved.model, "qwen2.5-coder");
+        assert_eq!(resolved.host, "http://localhost:11434");
+        assert_eq!(resolved.transforms, Vec::new());
+        assert_eq!(
+            resolve_config(Some("deepseek-r1:14b".to_string()), None, None, None).transforms,
+            vec![Transform::StripThinking]
+        );
+    }
+
+    #[test]
+    fn profiles_inherit_and_override_the_top_level() {
+        let config: FileConfig = toml::from_str(
+            r#"
+            model = "qwen2.5-coder"
+            host = "http://localhost:11434"
+
+            [profiles.work]
+            host = "http://gpu.corp:11434"
+            model = "deepseek-coder-v2"
+
+            [profiles.work-small]
+            inherits = "work"
+            model = "qwen2.5-coder:7b"
+            "#,
+        )
+        .unwrap();
+
+        let applied = config.apply_profile("work-small").unwrap();
+        assert_eq!(applied.model.as_deref(), Some("qwen2.5-coder:7b"));
+        assert_eq!(applied.host.as_deref(), Some("http://gpu.corp:11434"));
+
+        let config: FileConfig = toml::from_str("[profiles.loop]\ninherits = \"loop\"\n").unwrap();
+        assert!(config.apply_profile("loop").is_err());
+    }
+
+    #[test]
+    fn most_specific_directory_mapping_wins() {
+        let config: FileConfig = toml::from_str(
+            r#"
+            [profiles.work]
+            directories = ["/src/corp"]
+
+            [profiles.oss]
+            directories = ["/src/corp/opensource", "/src/oss"]
+            "#,
+        )
+        .unwrap();
+
+        let select = |cwd: &str| config.select_profile(None, Path::new(cwd));
+        assert_eq!(select("/src/corp/billing").as_deref(), Some("work"));
+        assert_eq!(select("/src/corp/opensource/lib").as_deref(), Some("oss"));
+        assert_eq!(select("/home/me"), None);
+        assert_eq!(
+            config
+                .select_profile(Some("work"), Path::new("/src/oss"))
+                .as_deref(),
+            Some("work")
+        );
+    }
+
+    #[test]
+    fn user_config_profiles_apply_in_project_subdirectories() {
+        let temp = tempfile::tempdir().unwrap();
+        let local = temp.path().join("project/src/.ai-coder.toml");
+        let user = temp.path().join("config.toml");
+        let project = temp.path().join("project");
+        fs::write(
+            &user,
+            format!(
+                "model = \"codellama\"\n\n[profiles.work]\nmodel = \"qwen2.5-coder\"\ndirectories = [{:?}]\n",
+                project.display()
+            ),
+        )
+        .unwrap();
+
+        let path = config_path(&local, Some(&user));
+        assert_eq!(path, user);
+        let (file, profile) = load_with_profile(&path, None, &project.join("src")).unwrap();
+        assert_eq!(profile.as_deref(), Some("work"));
+        assert_eq!(file.unwrap().model.as_deref(), Some("qwen2.5-coder"));
+
+        fs::create_dir_all(local.parent().unwrap()).unwrap();
+        fs::write(&local, "").unwrap();
+        assert_eq!(config_path(&local, Some(&user)), local);
+    }
+
+    #[test]
+    fn save_defaults_keeps_profiles_and_comments() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".ai-coder.toml");
+        fs::write(
+            &path,
+            "# shared\nmodel = \"codellama\"\n\n[profiles.work]\nhost = \"http://gpu:11434\"\n",
+        )
+        .unwrap();
+
+        save_defaults(&path, "qwen2.5-coder:7b", "http://localhost:11434").unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().starts_with("# shared\n"));
+        let config = load_file_config(&path).unwrap();
+        assert_eq!(config.model.as_deref(), Some("qwen2.5-coder:7b"));
+        assert_eq!(config.host.as_deref(), Some("http://localhost:11434"));
+        assert_eq!(
+            config.profiles["work"].host.as_deref(),
+            Some("http://gpu:11434")
+        );
+    }
+
+    #[test]
+    fn save_model_writes_into_the_profile() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".ai-coder.toml");
+        fs::write(
+            &path,
+            "model = \"codellama\"\n\n[profiles.work]\nhost = \"http://gpu:11434\"\n",
+        )
+        .unwrap();
+
+        save_model(&path, Some("work"), "qwen2.5-coder:7b-instruct-q8_0").unwrap();
+
+        let config = load_file_config(&path).unwrap();
+        assert_eq!(config.model.as_deref(), Some("codellama"));
+        assert_eq!(
+            config.profiles["work"].model.as_deref(),
+            Some("qwen2.5-coder:7b-instruct-q8_0")
+        );
+        assert_eq!(
+            config.profiles["work"].host.as_deref(),
+            Some("http://gpu:11434")
+        );
+    }
+
+    #[test]
+    fn save_num_ctx_adds_a_model_section() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".ai-coder.toml");
+        fs::write(
+            &path,
+            "model = \"qwen2.5-coder:7b\"\n\n[models.deepseek-r1]\ntransforms = [\"strip-thinking\"]\n",
+        )
+        .unwrap();
+
+        save_num_ctx(&path, "qwen2.5-coder:7b", 16384).unwrap();
+        save_num_ctx(&path, "deepseek-r1", 8192).unwrap();
+
+        let config = load_file_config(&path).unwrap();
+        let resolved = resolve_config(None, None, None, Some(config));
+        assert_eq!(resolved.num_ctx, Some(16384));
+        let config = load_file_config(&path).unwrap();
+        let deepseek = config.model_settings("deepseek-r1:14b").unwrap();
+        assert_eq!(deepseek.num_ctx, Some(8192));
+        assert_eq!(deepseek.transforms, Some(vec![Transform::StripThinking]));
+    }
+
+    #[test]
+    fn transforms_come_from_the_most_specific_model_section() {
+        let config: FileConfig = toml::from_str(
+            r#"
+            model = "deepseek-r1:14b"
+
+            [models.deepseek]
+            transforms = ["collapse-repeats"]
+
+            [models.deepseek-r1]
+            transforms = ["strip-thinking", "normalize-fences"]
+            "#,
+        )
+        .unwrap();
+
+        let resolved = resolve_config(None, None, None, Some(config));
+
+        assert_eq!(
+            resolved.transforms,
+            vec![Transform::StripThinking, Transform::NormalizeFences]
+        );
+    }
+}
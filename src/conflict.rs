@@ -0,0 +1,357 @@
+use crate::action;
+use crate::error::RuntimeError;
+use crate::git;
+use crate::ollama::GenerateRequest;
+use crate::terminal;
+use crate::AiCoder;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lines on each side of a conflict shown to the model.
+const CONTEXT_LINES: usize = 15;
+
+/// One `<<<<<<<` ... `>>>>>>>` block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// One-based line of the `<<<<<<<` marker.
+    pub line: usize,
+    pub ours_label: String,
+    pub ours: String,
+    /// The common ancestor, with `merge.conflictStyle = diff3` or `zdiff3`.
+    pub base: Option<String>,
+    pub theirs_label: String,
+    pub theirs: String,
+    /// The block as it appears in the file, markers included.
+    pub raw: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Text(String),
+    Conflict(Conflict),
+}
+
+fn marker<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(marker)?;
+    let rest = rest.trim_end_matches(['\n', '\r']);
+    (rest.is_empty() || rest.starts_with(' ')).then(|| rest.trim_start())
+}
+
+/// Splits a file into plain text and conflict blocks.
+pub fn parse(contents: &str) -> Result<Vec<Segment>, RuntimeError> {
+    enum Part {
+        Ours,
+        Base,
+        Theirs,
+    }
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut current: Option<(Conflict, Part)> = None;
+
+    for (index, line) in contents.split_inclusive('\n').enumerate() {
+        let Some((conflict, part)) = current.as_mut() else {
+            if let Some(label) = marker(line, "<<<<<<<") {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+                let conflict = Conflict {
+                    line: index + 1,
+                    ours_label: label.to_string(),
+                    ours: String::new(),
+                    base: None,
+                    theirs_label: String::new(),
+                    theirs: String::new(),
+                    raw: line.to_string(),
+                };
+                current = Some((conflict, Part::Ours));
+            } else {
+                text.push_str(line);
+            }
+            continue;
+        };
+        conflict.raw.push_str(line);
+        match part {
+            Part::Ours | Part::Base if marker(line, "=======").is_some() => *part = Part::Theirs,
+            Part::Ours if marker(line, "|||||||").is_some() => {
+                conflict.base = Some(String::new());
+                *part = Part::Base;
+            }
+            Part::Ours => conflict.ours.push_str(line),
+            Part::Base => conflict.base.get_or_insert_with(String::new).push_str(line),
+            Part::Theirs => match marker(line, ">>>>>>>") {
+                Some(label) => {
+                    conflict.theirs_label = label.to_string();
+                    let (conflict, _) = current.take().expect("inside a conflict");
+                    segments.push(Segment::Conflict(conflict));
+                }
+                None => conflict.theirs.push_str(line),
+            },
+        }
+    }
+    if let Some((conflict, _)) = current {
+        return Err(RuntimeError::Usage(format!(
+            "the conflict starting on line {} is not closed",
+            conflict.line
+        )));
+    }
+    segments.push(Segment::Text(text));
+    segments.retain(|segment| !matches!(segment, Segment::Text(text) if text.is_empty()));
+    Ok(segments)
+}
+
+/// Files git reports as unmerged, relative to the current directory.
+pub fn conflicted_files() -> Result<Vec<PathBuf>, RuntimeError> {
+    let root = PathBuf::from(git::run(&["rev-parse", "--show-toplevel"])?.trim_end());
+    let files = git::run(&["diff", "--name-only", "--diff-filter=U"])?;
+    let cwd = std::env::current_dir()?;
+    Ok(files
+        .lines()
+        .map(|file| {
+            let path = root.join(file);
+            path.strip_prefix(&cwd)
+                .map(Path::to_path_buf)
+                .unwrap_or(path)
+        })
+        .collect())
+}
+
+/// The last `lines` lines of `text`, or the first ones with `from_end`
+/// false.
+fn context(text: &str, lines: usize, from_end: bool) -> String {
+    let all: Vec<&str> = text.split_inclusive('\n').collect();
+    let kept = if from_end {
+        &all[all.len().saturating_sub(lines)..]
+    } else {
+        &all[..lines.min(all.len())]
+    };
+    kept.concat()
+}
+
+pub fn build_prompt(file: &Path, before: &str, conflict: &Conflict, after: &str) -> String {
+    let language = action::language_for(file).unwrap_or_default();
+    let base = match &conflict.base {
+        Some(base) => format!("Common ancestor of both sides:\n```{language}\n{base}```\n\n"),
+        None => String::new(),
+    };
+    format!(
+        "Resolve this merge conflict in {file}, at line {line}.\n\
+         Combine the intent of both sides: keep every change that still makes sense, \
+         and prefer {theirs} only where the two truly contradict. \
+         Reply with only the code that replaces the conflict, markers removed, \
+         in a single fenced code block. Do not repeat the code before or after it.\n\n\
+         Code before the conflict:\n```{language}\n{before}```\n\n\
+         Our side ({ours}):\n```{language}\n{ours_code}```\n\n\
+         {base}\
+         Their side ({theirs}):\n```{language}\n{theirs_code}```\n\n\
+         Code after the conflict:\n```{language}\n{after}```\n",
+        file = file.display(),
+        line = conflict.line,
+        ours = conflict.ours_label,
+        theirs = conflict.theirs_label,
+        ours_code = conflict.ours,
+        theirs_code = conflict.theirs,
+    )
+}
+
+/// Lines of `resolution` that are still conflict markers, one-based.
+pub fn leftover_markers(resolution: &str) -> Vec<usize> {
+    resolution
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            ["<<<<<<<", "|||||||", "=======", ">>>>>>>"]
+                .iter()
+                .any(|prefix| marker(line, prefix).is_some())
+        })
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+/// The conflict block as removed lines followed by its resolution as
+/// added lines.
+pub fn preview(conflict: &Conflict, resolution: &str) -> String {
+    let mut preview = format!("@@ line {} @@\n", conflict.line);
+    for line in conflict.raw.lines() {
+        preview.push_str(&format!("-{line}\n"));
+    }
+    for line in resolution.lines() {
+        preview.push_str(&format!("+{line}\n"));
+    }
+    preview
+}
+
+/// The model's replacement for one conflict, ending in a newline unless
+/// it is empty.
+async fn resolve_conflict(
+    coder: &AiCoder,
+    request: &GenerateRequest,
+) -> Result<String, RuntimeError> {
+    let completion = coder.generate(request).await?;
+    let mut resolution = action::extract_code(&completion.text);
+    if !resolution.is_empty() && !resolution.ends_with('\n') {
+        resolution.push('\n');
+    }
+    Ok(resolution)
+}
+
+/// Asks the model to resolve each conflict in `file`. Returns the resolved
+/// contents and a preview of the changes.
+async fn resolve_file(coder: &AiCoder, file: &Path) -> Result<(String, String), RuntimeError> {
+    let segments = parse(&fs::read_to_string(file)?)?;
+    let system = action::system_prompt_for(coder, file)?;
+    let (mut resolved, mut previews) = (String::new(), String::new());
+    for (index, segment) in segments.iter().enumerate() {
+        let conflict = match segment {
+            Segment::Text(text) => {
+                resolved.push_str(text);
+                continue;
+            }
+            Segment::Conflict(conflict) => conflict,
+        };
+        let before = match index.checked_sub(1).map(|i| &segments[i]) {
+            Some(Segment::Text(text)) => context(text, CONTEXT_LINES, true),
+            _ => String::new(),
+        };
+        let after = match segments.get(index + 1) {
+            Some(Segment::Text(text)) => context(text, CONTEXT_LINES, false),
+            _ => String::new(),
+        };
+        let mut request = GenerateRequest {
+            model: coder.config().model.clone(),
+            prompt: build_prompt(file, &before, conflict, &after),
+            system: system.clone(),
+            ..Default::default()
+        };
+        let mut resolution = resolve_conflict(coder, &request).await?;
+        if !leftover_markers(&resolution).is_empty() {
+            eprintln!(
+                "[ai-coder] The resolution of line {} kept conflict markers; asking again",
+                conflict.line
+            );
+            request.prompt.push_str(
+                "\nYour previous answer still contained conflict marker lines \
+                 (<<<<<<<, =======, >>>>>>>). Merge both sides into plain code without them.\n",
+            );
+            resolution = resolve_conflict(coder, &request).await?;
+        }
+        if let Some(line) = leftover_markers(&resolution).first() {
+            return Err(RuntimeError::Usage(format!(
+                "the resolution of the conflict on line {} still has a conflict marker \
+                 on its line {line}",
+                conflict.line
+            )));
+        }
+        previews.push_str(&preview(conflict, &resolution));
+        resolved.push_str(&resolution);
+    }
+    Ok((resolved, previews))
+}
+
+/// Resolves every conflicted file in turn, writing each one after the user
+/// approves its preview (or right away with `assume_yes`). Files are left
+/// for the user to review and `git add`.
+pub async fn run(coder: &AiCoder, assume_yes: bool) -> Result<(), RuntimeError> {
+    let files = conflicted_files()?;
+    if files.is_empty() {
+        return Err(RuntimeError::Usage(
+            "no conflicted files; run this during a merge or rebase".to_string(),
+        ));
+    }
+    let mut resolved = Vec::new();
+    for file in &files {
+        eprintln!("[ai-coder] Resolving {}", file.display());
+        let (contents, previews) = match resolve_file(coder, file).await {
+            Ok(result) => result,
+            Err(RuntimeError::Usage(message)) => {
+                eprintln!("[ai-coder] Skipping {}: {message}", file.display());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if previews.is_empty() {
+            eprintln!("[ai-coder] {} has no conflict markers left", file.display());
+            continue;
+        }
+        eprint!("{previews}");
+        let question = format!("Write this resolution to {}?", file.display());
+        if assume_yes || terminal::confirm(&question, false, false)? {
+            fs::write(file, contents)?;
+            resolved.push(file.display().to_string());
+        }
+    }
+    if !resolved.is_empty() {
+        eprintln!(
+            "[ai-coder] Review the result, then: git add {}",
+            resolved.join(" ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_prompt, leftover_markers, parse, preview, Segment};
+    use std::path::Path;
+
+    const MERGED: &str = "fn main() {\n\
+                          <<<<<<< HEAD\n    run(1);\n\
+                          ||||||| base\n    run();\n\
+                          =======\n    run_fast();\n\
+                          >>>>>>> feature\n}\n";
+
+    #[test]
+    fn parses_conflicts_with_their_labels_and_base() {
+        let segments = parse(MERGED).unwrap();
+
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], Segment::Text("fn main() {\n".to_string()));
+        let Segment::Conflict(conflict) = &segments[1] else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(conflict.line, 2);
+        assert_eq!(
+            (conflict.ours_label.as_str(), conflict.theirs_label.as_str()),
+            ("HEAD", "feature")
+        );
+        assert_eq!(conflict.ours, "    run(1);\n");
+        assert_eq!(conflict.base.as_deref(), Some("    run();\n"));
+        assert_eq!(conflict.theirs, "    run_fast();\n");
+        assert_eq!(segments[2], Segment::Text("}\n".to_string()));
+    }
+
+    #[test]
+    fn unclosed_conflicts_are_rejected() {
+        let err = parse("a\n<<<<<<< HEAD\nb\n=======\nc\n").unwrap_err();
+
+        assert!(err.to_string().contains("line 2"));
+        assert_eq!(
+            parse("no conflicts\n").unwrap(),
+            vec![Segment::Text("no conflicts\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn prompt_and_preview_show_both_sides() {
+        let segments = parse(MERGED).unwrap();
+        let Segment::Conflict(conflict) = &segments[1] else {
+            panic!("expected a conflict");
+        };
+
+        let prompt = build_prompt(Path::new("src/main.rs"), "fn main() {\n", conflict, "}\n");
+        assert!(prompt.contains("Our side (HEAD):\n```rust\n    run(1);\n```"));
+        assert!(prompt.contains("Common ancestor of both sides:\n```rust\n    run();\n```"));
+        assert!(prompt.contains("Their side (feature):\n```rust\n    run_fast();\n```"));
+
+        let preview = preview(conflict, "    run_fast(1);\n");
+        assert!(preview.starts_with("@@ line 2 @@\n-<<<<<<< HEAD\n"));
+        assert!(preview.ends_with("->>>>>>> feature\n+    run_fast(1);\n"));
+    }
+
+    #[test]
+    fn resolutions_that_keep_markers_are_caught() {
+        let kept = "    run(1);\n=======\n    run_fast();\n>>>>>>> feature\n";
+
+        assert_eq!(leftover_markers(kept), vec![2, 4]);
+        assert_eq!(leftover_markers("    run_fast(1);\n"), Vec::<usize>::new());
+        // Only whole marker lines count, not `==` runs inside code.
+        assert!(leftover_markers("let same = a ======= b;\n// =======>\n").is_empty());
+    }
+}
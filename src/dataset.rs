@@ -0,0 +1,269 @@
+use crate::error::RuntimeError;
+use crate::redact::redact;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where comparisons and ratings are kept for building eval and
+/// fine-tuning datasets: `$XDG_DATA_HOME/ai-coder`, falling back to
+/// `~/.local/share/ai-coder`.
+pub fn data_dir() -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_dir.join("ai-coder"))
+}
+
+pub const COMPARISONS_FILE: &str = "comparisons.jsonl";
+pub const FEEDBACK_FILE: &str = "feedback.jsonl";
+
+/// One model's answer in a comparison.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub model: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// The same prompt answered by several models.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub prompt: String,
+    pub answers: Vec<Answer>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    /// Reads a typed answer: `+`/`y`/`up` or `-`/`n`/`down`. Anything else
+    /// (including nothing) skips rating.
+    pub fn parse(answer: &str) -> Option<Rating> {
+        match answer.trim().to_lowercase().as_str() {
+            "+" | "y" | "yes" | "up" | "good" => Some(Rating::Up),
+            "-" | "n" | "no" | "down" | "bad" => Some(Rating::Down),
+            _ => None,
+        }
+    }
+}
+
+/// A user's rating of one answer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Feedback {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub prompt: String,
+    pub response: String,
+    pub rating: Rating,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Training-data layouts `export_finetune` can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinetuneFormat {
+    /// `{"conversations": [{"from": "human", ...}, {"from": "gpt", ...}]}`
+    ShareGpt,
+    /// `{"instruction", "input", "output", "system"}`
+    Alpaca,
+    /// Every rating, good and bad, with its note: for preference tuning
+    /// (e.g. KTO) and evals.
+    Jsonl,
+}
+
+impl FinetuneFormat {
+    pub const NAMES: [&'static str; 3] = ["sharegpt", "alpaca", "jsonl"];
+
+    pub fn from_name(name: &str) -> Option<FinetuneFormat> {
+        match name {
+            "sharegpt" => Some(FinetuneFormat::ShareGpt),
+            "alpaca" => Some(FinetuneFormat::Alpaca),
+            "jsonl" => Some(FinetuneFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// One training example per line in `format`, with secrets and email
+/// addresses redacted. ShareGPT and Alpaca are for supervised tuning, so
+/// only answers rated up are included.
+pub fn export_finetune(feedback: &[Feedback], format: FinetuneFormat) -> Vec<Value> {
+    feedback
+        .iter()
+        .filter(|item| format == FinetuneFormat::Jsonl || item.rating == Rating::Up)
+        .map(|item| {
+            let system = item.system.as_deref().map(redact);
+            let prompt = redact(&item.prompt);
+            let response = redact(&item.response);
+            match format {
+                FinetuneFormat::ShareGpt => {
+                    let mut conversations = Vec::new();
+                    if let Some(system) = system {
+                        conversations.push(json!({ "from": "system", "value": system }));
+                    }
+                    conversations.push(json!({ "from": "human", "value": prompt }));
+                    conversations.push(json!({ "from": "gpt", "value": response }));
+                    json!({ "conversations": conversations })
+                }
+                FinetuneFormat::Alpaca => json!({
+                    "instruction": prompt,
+                    "input": "",
+                    "output": response,
+                    "system": system.unwrap_or_default(),
+                }),
+                FinetuneFormat::Jsonl => json!({
+                    "model": item.model,
+                    "system": system,
+                    "prompt": prompt,
+                    "response": response,
+                    "rating": item.rating,
+                    "note": item.note.as_deref().map(redact),
+                }),
+            }
+        })
+        .collect()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Appends `record` as one JSON line to `dir/file`, creating both as
+/// needed. Returns the file's path.
+pub fn append<T: Serialize>(dir: &Path, file: &str, record: &T) -> Result<PathBuf, RuntimeError> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(file);
+    let mut line = serde_json::to_string(record)
+        .map_err(|err| RuntimeError::Usage(format!("cannot encode record: {err}")))?;
+    line.push('\n');
+    // One write per record, so concurrent runs don't interleave lines.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(line.as_bytes())?;
+    Ok(path)
+}
+
+/// Every record in `dir/file`; a missing file has none. Lines that don't
+/// parse (e.g. cut short by a crash) are skipped.
+pub fn read<T: for<'de> Deserialize<'de>>(dir: &Path, file: &str) -> Result<Vec<T>, RuntimeError> {
+    let contents = match fs::read_to_string(dir.join(file)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append, export_finetune, read, Answer, Comparison, Feedback, FinetuneFormat, Rating,
+        COMPARISONS_FILE,
+    };
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn records_round_trip_and_broken_lines_are_skipped() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("ai-coder");
+        let comparison = Comparison {
+            timestamp: 1_700_000_000,
+            prompt: "Explain lifetimes".to_string(),
+            answers: vec![Answer {
+                model: "qwen2.5-coder:7b".to_string(),
+                text: "A lifetime is...".to_string(),
+                completion_tokens: Some(120),
+                duration_ms: None,
+            }],
+        };
+
+        let path = append(&dir, COMPARISONS_FILE, &comparison).unwrap();
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "{\"timestamp\":\n",
+        )
+        .unwrap();
+        append(&dir, COMPARISONS_FILE, &comparison).unwrap();
+
+        let records: Vec<Comparison> = read(&dir, COMPARISONS_FILE).unwrap();
+        assert_eq!(records, vec![comparison.clone(), comparison]);
+        assert!(read::<Comparison>(&dir, "missing.jsonl")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn ratings_are_read_from_short_answers_and_stored_lowercase() {
+        assert_eq!(Rating::parse(" + "), Some(Rating::Up));
+        assert_eq!(Rating::parse("Down"), Some(Rating::Down));
+        assert_eq!(Rating::parse(""), None);
+
+        let feedback = Feedback {
+            timestamp: 1,
+            model: "qwen2.5-coder".to_string(),
+            system: None,
+            prompt: "p".to_string(),
+            response: "r".to_string(),
+            rating: Rating::Down,
+            note: Some("wrong crate".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&feedback).unwrap(),
+            r#"{"timestamp":1,"model":"qwen2.5-coder","prompt":"p","response":"r","rating":"down","note":"wrong crate"}"#
+        );
+    }
+
+    #[test]
+    fn supervised_formats_keep_only_good_answers_redacted() {
+        let rated = |rating, response: &str| Feedback {
+            timestamp: 1,
+            model: "m".to_string(),
+            system: Some("Be brief.".to_string()),
+            prompt: "Mail ana@example.com".to_string(),
+            response: response.to_string(),
+            rating,
+            note: None,
+        };
+        let feedback = [rated(Rating::Up, "Sent."), rated(Rating::Down, "No.")];
+
+        assert_eq!(
+            export_finetune(&feedback, FinetuneFormat::ShareGpt),
+            vec![json!({ "conversations": [
+                { "from": "system", "value": "Be brief." },
+                { "from": "human", "value": "Mail [EMAIL]" },
+                { "from": "gpt", "value": "Sent." },
+            ]})]
+        );
+        assert_eq!(
+            export_finetune(&feedback, FinetuneFormat::Alpaca)[0]["instruction"],
+            "Mail [EMAIL]"
+        );
+        let all = export_finetune(&feedback, FinetuneFormat::Jsonl);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1]["rating"], "down");
+    }
+}
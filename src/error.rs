@@ -0,0 +1,240 @@
+use crate::ollama::Capability;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The provider could not be reached at all.
+    Connect(reqwest::Error),
+    /// The connection failed after it was established (timeouts, resets).
+    Transport(reqwest::Error),
+    /// The provider rejected the request or reported an error mid-stream.
+    Provider {
+        status: Option<u16>,
+        message: String,
+    },
+    ModelNotFound {
+        model: String,
+    },
+    /// The model lacks a capability the request needs, e.g. vision.
+    Unsupported {
+        model: String,
+        capability: Capability,
+    },
+    /// The prompt does not fit in the model's context window.
+    ContextOverflow {
+        model: String,
+        message: String,
+    },
+    /// The provider ran out of GPU/system memory loading or running the model.
+    ResourceExhausted {
+        model: String,
+        message: String,
+    },
+    /// A response frame was not valid JSON of the expected shape.
+    Decode(serde_json::Error),
+    Config {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    Io(io::Error),
+    /// Invalid command-line input that clap cannot catch on its own.
+    Usage(String),
+    /// Some files in a batch failed; each was reported as it finished.
+    BatchFailed {
+        failed: usize,
+        total: usize,
+    },
+}
+
+impl RuntimeError {
+    /// Whether repeating the same request may succeed without user action.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RuntimeError::Connect(_) | RuntimeError::Transport(_) => true,
+            RuntimeError::Provider {
+                status: Some(status),
+                ..
+            } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// An actionable next step to print alongside the error.
+    pub fn user_hint(&self) -> Option<String> {
+        match self {
+            RuntimeError::Connect(_) => Some(
+                "is Ollama running? Start it with `ollama serve`, or point --host / OLLAMA_HOST at your instance".to_string(),
+            ),
+            RuntimeError::Transport(err) if err.is_timeout() => Some(
+                "the model may still be loading; try again or pick a smaller model".to_string(),
+            ),
+            RuntimeError::ModelNotFound { model } => Some(format!("run `ollama pull {model}`")),
+            RuntimeError::Unsupported {
+                capability: Capability::Vision,
+                ..
+            } => Some("use a vision model such as llava or qwen2.5vl with --model".to_string()),
+            RuntimeError::Unsupported { model, .. } => Some(format!(
+                "pick another model with --model; `ollama show {model}` lists what it supports"
+            )),
+            RuntimeError::ContextOverflow { model, .. } => Some(format!(
+                "shorten the prompt, or raise the context window (num_ctx) for {model} if it supports more"
+            )),
+            RuntimeError::ResourceExhausted { model, .. } => {
+                let family = model.split(':').next().unwrap_or(model);
+                Some(format!(
+                    "{model} does not fit in available memory; stop other models (`ollama ps`) or pull a smaller or more quantized tag of {family} and pass it with --model"
+                ))
+            }
+            RuntimeError::Provider {
+                status: Some(status),
+                ..
+            } if *status >= 500 => Some("check the Ollama server logs".to_string()),
+            RuntimeError::Decode(_) => Some(
+                "check that the host is an Ollama server and that Ollama is up to date".to_string(),
+            ),
+            RuntimeError::Config { .. } => {
+                Some("fix the file, or pass --config to use a different one".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::Connect(err) => match err.url() {
+                Some(url) => write!(
+                    f,
+                    "could not connect to {}",
+                    url.origin().ascii_serialization()
+                ),
+                None => write!(f, "could not connect to the provider"),
+            },
+            RuntimeError::Transport(_) => write!(f, "request to the provider failed"),
+            RuntimeError::Provider {
+                status: Some(status),
+                message,
+            } => write!(f, "provider returned {status}: {message}"),
+            RuntimeError::Provider {
+                status: None,
+                message,
+            } => write!(f, "provider error: {message}"),
+            RuntimeError::ModelNotFound { model } => write!(f, "model '{model}' not found"),
+            RuntimeError::Unsupported { model, capability } => {
+                write!(f, "model '{model}' does not support {capability}")
+            }
+            RuntimeError::ContextOverflow { model, message } => {
+                write!(f, "prompt exceeds the context window of {model}: {message}")
+            }
+            RuntimeError::ResourceExhausted { model, message } => {
+                write!(f, "provider ran out of memory running {model}: {message}")
+            }
+            RuntimeError::Decode(_) => write!(f, "could not decode the provider response"),
+            RuntimeError::Config { path, .. } => {
+                write!(f, "invalid config file {}", path.display())
+            }
+            RuntimeError::Io(_) => write!(f, "I/O error"),
+            RuntimeError::Usage(message) => write!(f, "{message}"),
+            RuntimeError::BatchFailed { failed, total } => {
+                write!(f, "{failed} of {total} files failed")
+            }
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RuntimeError::Connect(err) | RuntimeError::Transport(err) => Some(err),
+            RuntimeError::Decode(err) => Some(err),
+            RuntimeError::Config { source, .. } => Some(source.as_ref()),
+            RuntimeError::Io(err) => Some(err),
+            RuntimeError::Provider { .. }
+            | RuntimeError::ModelNotFound { .. }
+            | RuntimeError::Unsupported { .. }
+            | RuntimeError::ContextOverflow { .. }
+            | RuntimeError::ResourceExhausted { .. }
+            | RuntimeError::Usage(_)
+            | RuntimeError::BatchFailed { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for RuntimeError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() {
+            RuntimeError::Connect(err)
+        } else {
+            RuntimeError::Transport(err)
+        }
+    }
+}
+
+impl From<serde_json::Error> for RuntimeError {
+    fn from(err: serde_json::Error) -> Self {
+        RuntimeError::Decode(err)
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        RuntimeError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeError;
+
+    #[test]
+    fn server_errors_and_rate_limits_are_retryable() {
+        let provider = |status| RuntimeError::Provider {
+            status: Some(status),
+            message: "boom".to_string(),
+        };
+
+        assert!(provider(503).is_retryable());
+        assert!(provider(429).is_retryable());
+        assert!(!provider(400).is_retryable());
+        assert!(!RuntimeError::ModelNotFound {
+            model: "qwen2.5-coder".to_string()
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn model_not_found_hints_at_pulling_the_model() {
+        let err = RuntimeError::ModelNotFound {
+            model: "qwen2.5-coder".to_string(),
+        };
+
+        assert_eq!(err.to_string(), "model 'qwen2.5-coder' not found");
+        assert_eq!(
+            err.user_hint().as_deref(),
+            Some("run `ollama pull qwen2.5-coder`")
+        );
+        let tagged = RuntimeError::ModelNotFound {
+            model: "qwen2.5-coder:14b-instruct-q4_K_M".to_string(),
+        };
+        assert_eq!(
+            tagged.user_hint().as_deref(),
+            Some("run `ollama pull qwen2.5-coder:14b-instruct-q4_K_M`")
+        );
+    }
+
+    #[test]
+    fn resource_exhausted_suggests_a_smaller_variant_of_the_same_model() {
+        let err = RuntimeError::ResourceExhausted {
+            model: "qwen2.5-coder:32b".to_string(),
+            message: "out of memory".to_string(),
+        };
+
+        let hint = err.user_hint().unwrap();
+        assert!(hint.starts_with("qwen2.5-coder:32b does not fit"));
+        assert!(hint.contains("smaller or more quantized tag of qwen2.5-coder and"));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::EffectiveConfig;
+use crate::error::RuntimeError;
+use crate::ollama::{self, Completion, GenerateRequest, GenerationOptions};
+use reqwest::Client;
+use serde::Deserialize;
+
+/// Fill-in-the-middle prompt formats. Each model family was trained with
+/// its own sentinel tokens, so the prompt is sent raw rather than through
+/// the chat template.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FimTemplate {
+    /// Qwen2.5-Coder and CodeGemma.
+    Qwen,
+    StarCoder,
+    CodeLlama,
+    DeepSeek,
+}
+
+impl FimTemplate {
+    pub fn for_model(model: &str) -> Option<Self> {
+        let model = model.to_lowercase();
+        if model.contains("qwen") || model.contains("codegemma") {
+            Some(FimTemplate::Qwen)
+        } else if model.contains("starcoder") {
+            Some(FimTemplate::StarCoder)
+        } else if model.contains("codellama") {
+            Some(FimTemplate::CodeLlama)
+        } else if model.contains("deepseek-coder") {
+            Some(FimTemplate::DeepSeek)
+        } else {
+            None
+        }
+    }
+
+    pub fn render(self, prefix: &str, suffix: &str) -> String {
+        match self {
+            FimTemplate::Qwen => {
+                format!("<|fim_prefix|>{prefix}<|fim_suffix|>{suffix}<|fim_middle|>")
+            }
+            FimTemplate::StarCoder => {
+                format!("<fim_prefix>{prefix}<fim_suffix>{suffix}<fim_middle>")
+            }
+            FimTemplate::CodeLlama => format!("<PRE> {prefix} <SUF>{suffix} <MID>"),
+            FimTemplate::DeepSeek => {
+                format!("<｜fim▁begin｜>{prefix}<｜fim▁hole｜>{suffix}<｜fim▁end｜>")
+            }
+        }
+    }
+
+    /// Tokens that end the middle section for this family.
+    fn stop_tokens(self) -> Vec<String> {
+        let tokens: &[&str] = match self {
+            FimTemplate::Qwen => &[
+                "<|endoftext|>",
+                "<|fim_prefix|>",
+                "<|fim_suffix|>",
+                "<|fim_middle|>",
+                "<|file_sep|>",
+            ],
+            FimTemplate::StarCoder => &["<|endoftext|>", "<fim_prefix>", "<file_sep>"],
+            FimTemplate::CodeLlama => &["<EOT>", "<PRE>", "<SUF>"],
+            FimTemplate::DeepSeek => &["<｜end▁of▁sentence｜>", "<｜fim▁begin｜>"],
+        };
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FimOptions {
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    /// Overrides detection from the model name.
+    pub template: Option<FimTemplate>,
+}
+
+/// Completes the code between `prefix` and `suffix` at a cursor position.
+pub async fn complete_fim(
+    client: &Client,
+    config: &EffectiveConfig,
+    prefix: &str,
+    suffix: &str,
+    options: &FimOptions,
+) -> Result<Completion, RuntimeError> {
+    let template = options
+        .template
+        .or_else(|| FimTemplate::for_model(&config.model))
+        .ok_or_else(|| {
+            RuntimeError::Usage(format!(
+                "no fill-in-the-middle template known for {}; set fim_template to qwen, starcoder, codellama, or deepseek",
+                config.model
+            ))
+        })?;
+
+    let request = GenerateRequest {
+        model: config.model.clone(),
+        prompt: template.render(prefix, suffix),
+        raw: true,
+        options: Some(GenerationOptions {
+            temperature: Some(options.temperature.unwrap_or(0.2)),
+            num_predict: Some(options.max_tokens.unwrap_or(128)),
+            stop: template.stop_tokens(),
+            seed: config.seed,
+            num_ctx: config.num_ctx,
+        }),
+        ..Default::default()
+    };
+    ollama::complete(client, &config.host, &request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FimTemplate;
+
+    #[test]
+    fn detects_template_from_model_name() {
+        assert_eq!(
+            FimTemplate::for_model("qwen2.5-coder:7b"),
+            Some(FimTemplate::Qwen)
+        );
+        assert_eq!(
+            FimTemplate::for_model("starcoder2:3b"),
+            Some(FimTemplate::StarCoder)
+        );
+        assert_eq!(
+            FimTemplate::for_model("codellama:7b-code"),
+            Some(FimTemplate::CodeLlama)
+        );
+        assert_eq!(FimTemplate::for_model("llama3.1"), None);
+    }
+
+    #[test]
+    fn renders_prefix_and_suffix_around_sentinels() {
+        assert_eq!(
+            FimTemplate::Qwen.render("fn add(a: i32, b: i32) -> i32 {\n    ", "\n}"),
+            "<|fim_prefix|>fn add(a: i32, b: i32) -> i32 {\n    <|fim_suffix|>\n}<|fim_middle|>"
+        );
+        assert_eq!(
+            FimTemplate::CodeLlama.render("def f():", "\n"),
+            "<PRE> def f(): <SUF>\n <MID>"
+        );
+    }
+}
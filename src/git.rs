@@ -0,0 +1,21 @@
+use crate::error::RuntimeError;
+use std::process::Command;
+
+/// Output of `git <args>`; a failure becomes a usage error with git's
+/// message.
+pub(crate) fn run(args: &[&str]) -> Result<String, RuntimeError> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(RuntimeError::Usage(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Output of `git diff <args>`.
+pub(crate) fn diff(args: &[&str]) -> Result<String, RuntimeError> {
+    run(&[&["diff"], args].concat())
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::process::Command;
+use std::thread;
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Memory a model can use, as far as we can tell without vendor SDKs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hardware {
+    /// Total VRAM of the largest NVIDIA GPU, in bytes.
+    pub gpu_memory: Option<u64>,
+    /// Total system RAM in bytes. On Apple Silicon this is also GPU memory.
+    pub system_memory: Option<u64>,
+    pub unified_memory: bool,
+    pub cpus: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recommendation {
+    /// Ollama tag; the default tags are Q4_K_M quantized.
+    pub model: &'static str,
+    pub reason: String,
+}
+
+/// Model sizes from largest to smallest with the memory they need to run
+/// comfortably at Q4_K_M with a default context window, in GiB.
+const TIERS: [(&str, u64); 5] = [
+    ("qwen2.5-coder:32b", 24),
+    ("qwen2.5-coder:14b", 12),
+    ("qwen2.5-coder:7b", 6),
+    ("qwen2.5-coder:3b", 3),
+    ("qwen2.5-coder:1.5b", 0),
+];
+
+pub fn detect() -> Hardware {
+    Hardware {
+        gpu_memory: nvidia_memory(),
+        system_memory: system_memory(),
+        unified_memory: cfg!(all(target_os = "macos", target_arch = "aarch64")),
+        cpus: thread::available_parallelism().map_or(1, usize::from),
+    }
+}
+
+/// Largest VRAM reported by `nvidia-smi`, if it is installed.
+fn nvidia_memory() -> Option<u64> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=memory.total", "--format=csv,noheader,nounits"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_nvidia_smi(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// `nvidia-smi` prints one line per GPU, in MiB.
+fn parse_nvidia_smi(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().parse::<u64>().ok())
+        .max()
+        .map(|mib| mib * 1024 * 1024)
+}
+
+fn system_memory() -> Option<u64> {
+    if cfg!(target_os = "macos") {
+        let output = Command::new("sysctl")
+            .args(["-n", "hw.memsize"])
+            .output()
+            .ok()?;
+        return String::from_utf8_lossy(&output.stdout).trim().parse().ok();
+    }
+    parse_meminfo(&fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// The largest model that fits. Dedicated VRAM is used when present;
+/// otherwise the model runs on CPU or unified memory, so leave room for
+/// the rest of the system.
+pub fn recommend(hardware: &Hardware) -> Recommendation {
+    let (budget, source) = match (hardware.gpu_memory, hardware.system_memory) {
+        (Some(vram), _) => (vram, "of GPU memory"),
+        (None, Some(ram)) if hardware.unified_memory => {
+            (ram * 2 / 3, "of unified memory usable by the GPU")
+        }
+        (None, Some(ram)) => (ram / 2, "of RAM free for a CPU-only model"),
+        (None, None) => (0, "of memory detected"),
+    };
+    let (model, _) = TIERS
+        .iter()
+        .find(|(_, needed)| budget >= needed * GIB)
+        .unwrap_or(&TIERS[TIERS.len() - 1]);
+
+    Recommendation {
+        model,
+        reason: format!("~{} GiB {source}, {} CPUs", budget / GIB, hardware.cpus),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_meminfo, parse_nvidia_smi, recommend, Hardware, GIB};
+
+    #[test]
+    fn parses_the_largest_gpu_and_total_ram() {
+        assert_eq!(parse_nvidia_smi("8192\n24576\n"), Some(24576 * 1024 * 1024));
+        assert_eq!(parse_nvidia_smi("NVIDIA-SMI has failed"), None);
+        assert_eq!(
+            parse_meminfo("MemTotal:       16303492 kB\nMemFree:  1 kB\n"),
+            Some(16303492 * 1024)
+        );
+    }
+
+    #[test]
+    fn recommends_the_largest_model_that_fits() {
+        let gpu = |gib| Hardware {
+            gpu_memory: Some(gib * GIB),
+            system_memory: Some(64 * GIB),
+            ..Default::default()
+        };
+        assert_eq!(recommend(&gpu(8)).model, "qwen2.5-coder:7b");
+        assert_eq!(recommend(&gpu(24)).model, "qwen2.5-coder:32b");
+
+        let cpu_only = Hardware {
+            system_memory: Some(16 * GIB),
+            cpus: 8,
+            ..Default::default()
+        };
+        let recommendation = recommend(&cpu_only);
+        assert_eq!(recommendation.model, "qwen2.5-coder:7b");
+        assert_eq!(
+            recommendation.reason,
+            "~8 GiB of RAM free for a CPU-only model, 8 CPUs"
+        );
+
+        assert_eq!(recommend(&Hardware::default()).model, "qwen2.5-coder:1.5b");
+    }
+}
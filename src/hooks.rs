@@ -0,0 +1,235 @@
+use crate::error::RuntimeError;
+use crate::git;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Staged diffs are cut to this many characters to stay within the
+/// context window; the summary line rarely needs more.
+const MAX_DIFF_CHARS: usize = 20_000;
+
+/// Identifies hooks written by `ai-coder hooks install`, so reinstalling
+/// replaces them but never someone else's hook.
+const MARKER: &str = "# Installed by `ai-coder hooks install`.";
+
+/// The staged changes, as `git commit` will record them.
+pub fn staged_diff() -> Result<String, RuntimeError> {
+    git::diff(&["--cached"])
+}
+
+/// `diff` cut to [`MAX_DIFF_CHARS`].
+fn truncated(diff: &str) -> String {
+    let mut diff = diff.trim_end().to_string();
+    if diff.len() > MAX_DIFF_CHARS {
+        let mut end = MAX_DIFF_CHARS;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        diff.truncate(end);
+        diff.push_str("\n[diff truncated]");
+    }
+    diff
+}
+
+pub fn build_prompt(diff: &str) -> String {
+    let diff = truncated(diff);
+    format!(
+        "Write a git commit message for the staged changes below.\n\
+         Start with a summary line in the imperative mood, under 72 characters. \
+         If the reason for the change isn't obvious from the diff, add a blank line \
+         and a short body explaining what changed and why. \
+         Reply with only the message, without a code block.\n\n\
+         ```diff\n{diff}\n```\n"
+    )
+}
+
+/// Asks for a quick pre-commit check of the staged changes: only problems
+/// worth stopping the commit for, then a verdict.
+pub fn build_review_prompt(diff: &str) -> String {
+    let diff = truncated(diff);
+    format!(
+        "Review the staged changes below before they are committed. Only report problems \
+         that should stop the commit: leftover debug output or breakpoints, secrets or \
+         credentials, merge conflict markers, code that clearly won't compile or run, and \
+         obvious bugs. Ignore style and anything you aren't sure about. \
+         List each problem on its own line starting with \"- \"; list nothing if there are none. \
+         Finish with exactly one line `Verdict: commit` or `Verdict: block`.\n\n\
+         ```diff\n{diff}\n```\n"
+    )
+}
+
+/// What the pre-commit review found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Review {
+    /// Whether the model asked to stop the commit.
+    pub block: bool,
+    pub problems: Vec<String>,
+}
+
+/// Reads the problems and the final `Verdict:` line of a review. `None` if
+/// the model gave no verdict.
+pub fn parse_review(reply: &str) -> Option<Review> {
+    let block = reply.lines().rev().find_map(|line| {
+        let line = line.trim().trim_matches(['*', '`']).to_lowercase();
+        match line
+            .strip_prefix("verdict:")?
+            .trim()
+            .trim_matches(['*', '`', '.'])
+        {
+            "commit" => Some(false),
+            "block" => Some(true),
+            _ => None,
+        }
+    })?;
+    let problems = reply
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("- "))
+        .map(|problem| problem.trim().to_string())
+        .filter(|problem| !problem.is_empty())
+        .collect();
+    Some(Review { block, problems })
+}
+
+/// The commit message file with `message` placed above what git put there
+/// (its template and the `#` help comments).
+pub fn with_message(message: &str, existing: &str) -> String {
+    format!("{}\n{existing}", message.trim())
+}
+
+fn quoted(program: &Path) -> String {
+    program.display().to_string().replace('\'', r"'\''")
+}
+
+fn hook_script(program: &Path) -> String {
+    let program = quoted(program);
+    format!(
+        "#!/bin/sh\n\
+         {MARKER}\n\
+         # Drafts the commit message from the staged changes. Skip it with\n\
+         # AI_CODER_SKIP_HOOK=1 git commit ...\n\
+         case \"$2\" in message|merge|squash|commit) exit 0 ;; esac\n\
+         [ -n \"$AI_CODER_SKIP_HOOK\" ] && exit 0\n\
+         '{program}' commit-message --write \"$1\" ||\n    \
+         echo \"ai-coder: could not draft a commit message\" >&2\n\
+         exit 0\n"
+    )
+}
+
+/// The `pre-commit` hook: a model review that stops the commit only when
+/// it finds a problem, not when the model can't be reached.
+fn review_hook_script(program: &Path) -> String {
+    let program = quoted(program);
+    format!(
+        "#!/bin/sh\n\
+         {MARKER}\n\
+         # Has the model review the staged changes. Skip it with\n\
+         # git commit --no-verify, or AI_CODER_SKIP_HOOK=1 git commit ...\n\
+         [ -n \"$AI_CODER_SKIP_HOOK\" ] && exit 0\n\
+         exec '{program}' review-staged\n"
+    )
+}
+
+/// Where git looks for hooks, honoring `core.hooksPath`.
+fn hooks_dir() -> Result<PathBuf, RuntimeError> {
+    let dir = git::run(&["rev-parse", "--git-path", "hooks"])?;
+    Ok(PathBuf::from(dir.trim_end()))
+}
+
+/// Installs a `prepare-commit-msg` hook that runs `program commit-message`
+/// and a `pre-commit` hook that runs `program review-staged`. Existing
+/// hooks that ai-coder didn't write are only replaced with `force`.
+pub fn install(program: &Path, force: bool) -> Result<Vec<PathBuf>, RuntimeError> {
+    let dir = hooks_dir()?;
+    fs::create_dir_all(&dir)?;
+    let hooks = [
+        (dir.join("prepare-commit-msg"), hook_script(program)),
+        (dir.join("pre-commit"), review_hook_script(program)),
+    ];
+    // Checked up front so a refusal doesn't leave one hook installed.
+    for (path, _) in &hooks {
+        if let Ok(existing) = fs::read_to_string(path) {
+            if !force && !existing.contains(MARKER) {
+                return Err(RuntimeError::Usage(format!(
+                    "{} already exists; pass --force to replace it",
+                    path.display()
+                )));
+            }
+        }
+    }
+    for (path, script) in &hooks {
+        fs::write(path, script)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(hooks.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_prompt, build_review_prompt, hook_script, parse_review, review_hook_script,
+        with_message, Review, MAX_DIFF_CHARS,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn long_diffs_are_truncated() {
+        let prompt = build_prompt(&"+é\n".repeat(MAX_DIFF_CHARS));
+
+        assert!(prompt.contains("[diff truncated]"));
+        assert!(prompt.len() < MAX_DIFF_CHARS + 1_000);
+        assert!(build_prompt("+a\n").contains("```diff\n+a\n```"));
+    }
+
+    #[test]
+    fn message_goes_above_the_template() {
+        let existing = "\n# Please enter the commit message for your changes.\n";
+
+        assert_eq!(
+            with_message("Add hooks\n\n", existing),
+            "Add hooks\n\n# Please enter the commit message for your changes.\n"
+        );
+    }
+
+    #[test]
+    fn hook_skips_commits_that_already_have_a_message() {
+        let script = hook_script(Path::new("/opt/it's/ai-coder"));
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("message|merge|squash|commit) exit 0"));
+        assert!(script.contains(r"'/opt/it'\''s/ai-coder' commit-message --write"));
+    }
+
+    #[test]
+    fn review_hook_can_be_bypassed() {
+        let script = review_hook_script(Path::new("/usr/bin/ai-coder"));
+
+        assert!(script.contains("[ -n \"$AI_CODER_SKIP_HOOK\" ] && exit 0"));
+        assert!(script.contains("exec '/usr/bin/ai-coder' review-staged\n"));
+        assert!(script.contains("--no-verify"));
+        assert!(build_review_prompt("+dbg!(x);\n").contains("```diff\n+dbg!(x);\n```"));
+    }
+
+    #[test]
+    fn reads_the_review_verdict() {
+        let reply = "- `dbg!(config)` left in src/main.rs\n\n**Verdict: block**\n";
+
+        assert_eq!(
+            parse_review(reply),
+            Some(Review {
+                block: true,
+                problems: vec!["`dbg!(config)` left in src/main.rs".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_review("Verdict: commit."),
+            Some(Review {
+                block: false,
+                problems: Vec::new(),
+            })
+        );
+        assert_eq!(parse_review("Looks fine."), None);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::error::RuntimeError;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, ClientBuilder, Proxy};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The `[http]` section: how ai-coder connects to the model server.
+/// Without it, `HTTP_PROXY`/`HTTPS_PROXY`/`NO_PROXY` are still honored.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// Proxy for all requests, e.g. `http://proxy.corp:3128`.
+    pub proxy: Option<String>,
+    /// Extra PEM root certificate, for servers behind an internal CA.
+    pub ca_certificate: Option<PathBuf>,
+    /// Connect through this Unix socket instead of TCP; `host` then only
+    /// supplies the scheme and `Host` header.
+    pub unix_socket: Option<PathBuf>,
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle pooled connection is kept for reuse (default 90).
+    /// Raise it when a TLS handshake to the server is slow.
+    pub pool_idle_timeout: Option<u64>,
+    /// Seconds between TCP keep-alive probes, so proxies and NATs don't
+    /// drop idle connections (and long quiet streams).
+    pub tcp_keepalive: Option<u64>,
+    /// Sent with every request, e.g. for an authenticating reverse proxy.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl HttpConfig {
+    /// A client builder with these settings applied, for callers that add
+    /// their own (e.g. a timeout).
+    pub fn client_builder(&self) -> Result<ClientBuilder, RuntimeError> {
+        let mut builder = Client::builder().default_headers(self.header_map()?);
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy).map_err(|err| {
+                RuntimeError::Usage(format!("invalid [http] proxy `{proxy}`: {err}"))
+            })?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &self.ca_certificate {
+            let certificate = Certificate::from_pem(&fs::read(path)?).map_err(|err| {
+                RuntimeError::Usage(format!(
+                    "invalid [http] ca_certificate {}: {err}",
+                    path.display()
+                ))
+            })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(seconds) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(Duration::from_secs(seconds));
+        }
+        if let Some(path) = &self.unix_socket {
+            builder = with_unix_socket(builder, path.clone())?;
+        }
+        Ok(builder)
+    }
+
+    pub fn client(&self) -> Result<Client, RuntimeError> {
+        self.client_builder()?
+            .build()
+            .map_err(|err| RuntimeError::Usage(format!("invalid [http] settings: {err}")))
+    }
+
+    fn header_map(&self) -> Result<HeaderMap, RuntimeError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let invalid = || RuntimeError::Usage(format!("invalid [http] header `{name}`"));
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+            let mut value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+            // Keeps tokens out of debug logs.
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(unix)]
+fn with_unix_socket(builder: ClientBuilder, path: PathBuf) -> Result<ClientBuilder, RuntimeError> {
+    Ok(builder.unix_socket(path))
+}
+
+#[cfg(not(unix))]
+fn with_unix_socket(_: ClientBuilder, _: PathBuf) -> Result<ClientBuilder, RuntimeError> {
+    Err(RuntimeError::Usage(
+        "[http] unix_socket is only supported on Unix".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpConfig;
+    use crate::error::RuntimeError;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn headers_are_validated_when_building_the_client() {
+        let config = HttpConfig {
+            proxy: Some("http://proxy.corp:3128".to_string()),
+            pool_max_idle_per_host: Some(4),
+            tcp_keepalive: Some(30),
+            headers: BTreeMap::from([("Authorization".to_string(), "Bearer t0k".to_string())]),
+            ..Default::default()
+        };
+        assert!(config.client().is_ok());
+
+        let bad = HttpConfig {
+            headers: BTreeMap::from([("Bad Header".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+        let err = bad.client().unwrap_err();
+        assert!(matches!(err, RuntimeError::Usage(_)));
+        assert!(err.to_string().contains("Bad Header"));
+    }
+
+    #[test]
+    fn parses_the_http_section() {
+        let config: HttpConfig = toml::from_str(
+            r#"
+            unix_socket = "/run/llama.sock"
+            pool_idle_timeout = 300
+            headers = { "X-Api-Key" = "secret" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.unix_socket.unwrap().to_str(),
+            Some("/run/llama.sock")
+        );
+        assert_eq!(config.headers["X-Api-Key"], "secret");
+        assert_eq!(config.pool_idle_timeout, Some(300));
+        assert_eq!(config.proxy, None);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::bench;
+use crate::config;
+use crate::error::RuntimeError;
+use crate::hardware;
+use crate::http::HttpConfig;
+use crate::ollama;
+use crate::terminal::confirm;
+use futures_util::future::join_all;
+use reqwest::Client;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Where local model servers usually listen: Ollama, llama.cpp's server,
+/// and LM Studio.
+pub const CANDIDATE_HOSTS: [&str; 3] = [
+    "http://localhost:11434",
+    "http://localhost:8080",
+    "http://localhost:1234",
+];
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Preferred defaults, most preferred first, matched as name prefixes.
+const PREFERRED_MODELS: [&str; 4] = ["qwen2.5-coder", "deepseek-coder", "codellama", "starcoder"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detected {
+    Ollama {
+        host: String,
+        models: Vec<String>,
+    },
+    /// Answers `/v1/models` but not the Ollama API, e.g. llama.cpp or LM
+    /// Studio. ai-coder can't talk to these yet.
+    OpenAiCompatible {
+        host: String,
+    },
+}
+
+/// What kind of server, if any, is listening at `host`. `client` should
+/// have a short timeout.
+pub async fn probe(client: &Client, host: &str) -> Option<Detected> {
+    if let Ok(models) = ollama::list_models(client, host).await {
+        return Some(Detected::Ollama {
+            host: host.to_string(),
+            models,
+        });
+    }
+    let response = client
+        .get(format!("{}/v1/models", host))
+        .send()
+        .await
+        .ok()?;
+    response
+        .status()
+        .is_success()
+        .then(|| Detected::OpenAiCompatible {
+            host: host.to_string(),
+        })
+}
+
+/// The installed model to suggest: the first coding model in preference
+/// order, otherwise the first one listed.
+pub fn default_model(models: &[String]) -> Option<&str> {
+    PREFERRED_MODELS
+        .iter()
+        .find_map(|preferred| models.iter().find(|name| name.starts_with(preferred)))
+        .or_else(|| models.first())
+        .map(String::as_str)
+}
+
+/// Parses a 1-based menu choice; an empty answer picks `default`.
+fn parse_choice(answer: &str, len: usize, default: usize) -> Option<usize> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Some(default);
+    }
+    match answer.parse::<usize>() {
+        Ok(choice) if (1..=len).contains(&choice) => Some(choice - 1),
+        _ => None,
+    }
+}
+
+/// Shows a numbered menu on stderr and reads the choice from stdin,
+/// asking again on invalid input. With `assume_yes` the default is taken.
+fn choose(title: &str, options: &[String], default: usize, assume_yes: bool) -> io::Result<usize> {
+    eprintln!("[ai-coder] {title}");
+    for (index, option) in options.iter().enumerate() {
+        let marker = if index == default { " (default)" } else { "" };
+        eprintln!("  {}. {option}{marker}", index + 1);
+    }
+    if assume_yes || options.len() == 1 {
+        return Ok(default);
+    }
+
+    let stdin = io::stdin();
+    loop {
+        eprint!("Choose [{}]: ", default + 1);
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Ok(default);
+        }
+        match parse_choice(&answer, options.len(), default) {
+            Some(choice) => return Ok(choice),
+            None => eprintln!("Enter a number from 1 to {}.", options.len()),
+        }
+    }
+}
+
+/// Pulls `model` onto `host`, showing progress on stderr.
+pub async fn pull(client: &Client, host: &str, model: &str) -> Result<(), RuntimeError> {
+    let mut last_status = String::new();
+    ollama::pull_model(client, host, model, |progress| {
+        if progress.status != last_status {
+            if !last_status.is_empty() {
+                eprintln!();
+            }
+            eprint!("[ai-coder] {}", progress.status);
+            last_status.clone_from(&progress.status);
+        }
+        let percent = progress
+            .completed
+            .zip(progress.total)
+            .and_then(|(completed, total)| (completed * 100).checked_div(total));
+        if let Some(percent) = percent {
+            eprint!("\r[ai-coder] {}: {percent:>3}%", progress.status);
+        }
+    })
+    .await?;
+    eprintln!();
+    Ok(())
+}
+
+/// Hosts to probe: the configured `host` (from `--host`, `OLLAMA_HOST` or
+/// the config file) first, then the usual local ports.
+fn hosts_to_probe(host: &str) -> Vec<String> {
+    let host = host.trim_end_matches('/');
+    std::iter::once(host)
+        .chain(
+            CANDIDATE_HOSTS
+                .into_iter()
+                .filter(|candidate| *candidate != host),
+        )
+        .map(str::to_string)
+        .collect()
+}
+
+/// Finds a local Ollama server, recommends (and offers to pull) a model
+/// that fits this machine, runs a short benchmark, and writes `model` and
+/// `host` to `config_path`. `host` is probed first and offered as the
+/// default server.
+pub async fn run(
+    http: &HttpConfig,
+    host: &str,
+    config_path: &Path,
+    assume_yes: bool,
+) -> Result<(), RuntimeError> {
+    let client = &http.client()?;
+    let candidates = hosts_to_probe(host);
+    eprintln!("[ai-coder] Looking for local model servers...");
+    let detected: Vec<Detected> = {
+        // Dropped before the benchmark so its idle connections are closed.
+        let probe_client = http
+            .client_builder()?
+            .timeout(PROBE_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| client.clone());
+        join_all(candidates.iter().map(|host| probe(&probe_client, host)))
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+
+    let mut servers = Vec::new();
+    for server in detected {
+        match server {
+            Detected::Ollama { host, models } => {
+                eprintln!("[ai-coder] Found Ollama at {host} ({} models)", models.len());
+                servers.push((host, models));
+            }
+            Detected::OpenAiCompatible { host } => eprintln!(
+                "[ai-coder] Found an OpenAI-compatible server at {host}; ai-coder only supports the Ollama API, so it is skipped"
+            ),
+        }
+    }
+    if servers.is_empty() {
+        return Err(RuntimeError::Usage(format!(
+            "no Ollama server found on {}; start one with `ollama serve`, or set --host / OLLAMA_HOST",
+            candidates.join(", ")
+        )));
+    }
+
+    let hosts: Vec<String> = servers.iter().map(|(host, _)| host.clone()).collect();
+    let (host, models) = &mut servers[choose("Ollama servers:", &hosts, 0, assume_yes)?];
+
+    let recommendation = hardware::recommend(&hardware::detect());
+    eprintln!(
+        "[ai-coder] Recommended for this machine: {} ({})",
+        recommendation.model, recommendation.reason
+    );
+    let installed = models.iter().any(|name| name == recommendation.model);
+    // Downloads are large, so only default to pulling when nothing is installed.
+    if !installed
+        && confirm(
+            &format!("Pull {} now?", recommendation.model),
+            models.is_empty(),
+            assume_yes,
+        )?
+    {
+        pull(client, host, recommendation.model).await?;
+        models.push(recommendation.model.to_string());
+    }
+
+    let suggested = if models.iter().any(|name| name == recommendation.model) {
+        Some(recommendation.model)
+    } else {
+        default_model(models)
+    };
+    let Some(suggested) = suggested else {
+        return Err(RuntimeError::Usage(format!(
+            "{host} has no models installed; run `ollama pull {}` and try again",
+            recommendation.model
+        )));
+    };
+    let default = models
+        .iter()
+        .position(|name| name == suggested)
+        .unwrap_or(0);
+    let model = &models[choose("Installed models:", models, default, assume_yes)?];
+
+    eprintln!("[ai-coder] Benchmarking {model} (the first run includes loading it)...");
+    match bench::measure_speed(client, host, model).await {
+        Ok(speed) => match speed {
+            Some(speed) => eprintln!("[ai-coder] {model} generates {speed:.1} tokens/s"),
+            None => eprintln!("[ai-coder] {model} responded"),
+        },
+        // Still worth saving the choice; the hint says how to fix the model.
+        Err(err) => {
+            eprintln!("[ai-coder] warning: benchmark failed: {err}");
+            if let Some(hint) = err.user_hint() {
+                eprintln!("[ai-coder] hint: {hint}");
+            }
+        }
+    }
+
+    config::save_defaults(config_path, model, host)?;
+    eprintln!(
+        "[ai-coder] Wrote model and host to {}. Try: ai-coder \"Explain what this project does\"",
+        config_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_model, hosts_to_probe, parse_choice, CANDIDATE_HOSTS};
+
+    #[test]
+    fn suggests_a_coding_model_when_one_is_installed() {
+        let models = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            default_model(&models(&["llama3:8b", "codellama:7b", "qwen2.5-coder:7b"])),
+            Some("qwen2.5-coder:7b")
+        );
+        assert_eq!(default_model(&models(&["llama3:8b"])), Some("llama3:8b"));
+        assert_eq!(default_model(&[]), None);
+    }
+
+    #[test]
+    fn menu_choices_are_one_based_with_an_empty_default() {
+        assert_eq!(parse_choice("\n", 3, 1), Some(1));
+        assert_eq!(parse_choice(" 3 ", 3, 0), Some(2));
+        assert_eq!(parse_choice("0", 3, 0), None);
+        assert_eq!(parse_choice("four", 3, 0), None);
+    }
+
+    #[test]
+    fn probes_the_configured_host_first() {
+        assert_eq!(
+            hosts_to_probe("http://gpubox:11434/"),
+            [&["http://gpubox:11434"], &CANDIDATE_HOSTS[..]].concat()
+        );
+        assert_eq!(
+            hosts_to_probe("http://localhost:8080"),
+            [
+                "http://localhost:8080",
+                "http://localhost:11434",
+                "http://localhost:1234",
+            ]
+        );
+    }
+}
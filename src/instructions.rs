@@ -0,0 +1,154 @@
+use crate::config;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Per-directory instruction files, in the order they are layered.
+const INSTRUCTION_FILES: [&str; 2] = ["AGENTS.md", ".ai-coder/instructions.md"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// User/org-wide instructions: `$XDG_CONFIG_HOME/ai-coder/instructions.md`
+/// (falling back to `~/.config/ai-coder/instructions.md`).
+pub fn global_instructions_path() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("instructions.md"))
+}
+
+/// Nearest ancestor of `start` containing a `.git` entry, if any.
+fn find_repo_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Collects instruction files from least to most specific:
+/// the global file, then each directory from the repo root down to `cwd`.
+/// Outside a git repository only `cwd` itself is searched.
+pub fn discover(cwd: &Path, global: Option<&Path>) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+
+    if let Some(global) = global {
+        if global.is_file() {
+            found.push(global.to_path_buf());
+        }
+    }
+
+    let root = find_repo_root(cwd).unwrap_or_else(|| cwd.to_path_buf());
+    let mut dirs: Vec<&Path> = cwd
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&root))
+        .collect();
+    dirs.reverse();
+
+    for dir in dirs {
+        for name in INSTRUCTION_FILES {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                found.push(candidate);
+            }
+        }
+    }
+
+    found
+}
+
+pub fn load(paths: &[PathBuf]) -> Result<Vec<InstructionFile>, std::io::Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        let content = fs::read_to_string(path)?;
+        if content.trim().is_empty() {
+            continue;
+        }
+        files.push(InstructionFile {
+            path: path.clone(),
+            content,
+        });
+    }
+    Ok(files)
+}
+
+/// Renders the layered files into a single system prompt. Later (more
+/// specific) files take precedence when instructions conflict.
+pub fn system_prompt(files: &[InstructionFile]) -> Option<String> {
+    if files.is_empty() {
+        return None;
+    }
+
+    let mut prompt = String::from(
+        "Follow these project instructions. When they conflict, later sections take precedence over earlier ones.\n",
+    );
+    for file in files {
+        prompt.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            file.path.display(),
+            file.content.trim()
+        ));
+    }
+    Some(prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, load, system_prompt};
+    use std::fs;
+
+    #[test]
+    fn layers_global_then_repo_root_then_subdir() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path().join("repo");
+        let subdir = repo.join("crates").join("core");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(subdir.join(".ai-coder")).unwrap();
+
+        let global = temp.path().join("global.md");
+        fs::write(&global, "org rules").unwrap();
+        fs::write(repo.join("AGENTS.md"), "repo rules").unwrap();
+        fs::write(subdir.join(".ai-coder/instructions.md"), "subdir rules").unwrap();
+        // Outside the repository root; must not be picked up.
+        fs::write(temp.path().join("AGENTS.md"), "parent rules").unwrap();
+
+        let paths = discover(&subdir, Some(&global));
+
+        assert_eq!(
+            paths,
+            vec![
+                global,
+                repo.join("AGENTS.md"),
+                subdir.join(".ai-coder/instructions.md"),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_searches_cwd_outside_a_repository() {
+        let temp = tempfile::tempdir().unwrap();
+        let cwd = temp.path().join("project");
+        fs::create_dir_all(&cwd).unwrap();
+        fs::write(temp.path().join("AGENTS.md"), "parent rules").unwrap();
+        fs::write(cwd.join("AGENTS.md"), "project rules").unwrap();
+
+        assert_eq!(discover(&cwd, None), vec![cwd.join("AGENTS.md")]);
+    }
+
+    #[test]
+    fn system_prompt_skips_empty_files_and_keeps_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let first = temp.path().join("first.md");
+        let empty = temp.path().join("empty.md");
+        let last = temp.path().join("last.md");
+        fs::write(&first, "use tabs").unwrap();
+        fs::write(&empty, "  \n").unwrap();
+        fs::write(&last, "use spaces").unwrap();
+
+        let files = load(&[first, empty, last]).unwrap();
+        let prompt = system_prompt(&files).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert!(prompt.find("use tabs").unwrap() < prompt.find("use spaces").unwrap());
+        assert_eq!(system_prompt(&[]), None);
+    }
+}
@@ -0,0 +1,469 @@
+//! Local, GPU-accelerated AI coding assistance on top of Ollama.
+//!
+//! The `ai-coder` binary is a thin consumer of this crate. Other Rust
+//! applications can embed the same functionality through [`AiCoder`]:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), ai_coder::RuntimeError> {
+//! let coder = ai_coder::AiCoder::builder()
+//!     .host("http://localhost:11434")
+//!     .model("qwen2.5-coder")
+//!     .build();
+//!
+//! let answer = coder.chat("Write a binary search in Rust").await?;
+//! println!("{}", answer.text);
+//! # Ok(())
+//! # }
+//! ```
+
+use balance::Balancer;
+use reqwest::Client;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use transform::{Pipeline, TransformWriter};
+
+pub mod action;
+pub mod balance;
+pub mod batch;
+pub mod bench;
+pub mod config;
+pub mod conflict;
+pub mod dataset;
+pub mod error;
+pub mod fim;
+mod git;
+pub mod hardware;
+pub mod hooks;
+pub mod http;
+pub mod init;
+pub mod instructions;
+pub mod license;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod modelfile;
+pub mod needle;
+pub mod ollama;
+pub mod output;
+pub mod prompts;
+pub mod redact;
+pub mod release;
+pub mod report;
+pub mod syntax;
+mod terminal;
+pub mod transform;
+pub mod tunnel;
+pub mod verify;
+
+pub use action::{ActionRequest, ActionResponse};
+pub use config::EffectiveConfig;
+pub use error::RuntimeError;
+pub use fim::FimOptions;
+pub use ollama::{
+    Capabilities, Capability, Completion, GenerateRequest, GenerationOptions, ResponseMetadata,
+};
+pub use transform::Transform;
+
+/// Entry point for embedding ai-coder: a configured provider connection
+/// plus the prompt conventions shared by the CLI, `action --stdio`, and
+/// the language server. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct AiCoder {
+    client: Client,
+    config: EffectiveConfig,
+    system_prompt: Option<String>,
+    project_instructions: bool,
+    language: Option<String>,
+    show_thinking: bool,
+    /// Shared by clones so load is counted across them.
+    balancer: Arc<Balancer>,
+}
+
+#[derive(Debug, Default)]
+pub struct AiCoderBuilder {
+    client: Option<Client>,
+    host: Option<String>,
+    model: Option<String>,
+    system_prompt: Option<String>,
+    project_instructions: bool,
+    language: Option<String>,
+    transforms: Option<Vec<Transform>>,
+    show_thinking: bool,
+    endpoints: Vec<String>,
+    seed: Option<u64>,
+    num_ctx: Option<u32>,
+}
+
+impl AiCoderBuilder {
+    /// Ollama base URL. Defaults to `http://localhost:11434`.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// More Ollama servers with the same model. Whole-answer requests (batch
+    /// files, editor edits) are spread across them and `host`; streamed
+    /// chat and completions always use `host`.
+    pub fn endpoints(mut self, hosts: Vec<String>) -> Self {
+        self.endpoints = hosts;
+        self
+    }
+
+    /// Defaults to `qwen2.5-coder`.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Reuse an existing HTTP client (proxies, timeouts, connection pool).
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// System prompt for [`AiCoder::chat`]; takes precedence over project
+    /// instructions.
+    pub fn system_prompt(mut self, system_prompt: Option<String>) -> Self {
+        self.system_prompt = system_prompt;
+        self
+    }
+
+    /// Load `AGENTS.md` / `.ai-coder/instructions.md` into the system
+    /// prompt. Off by default for library use.
+    pub fn project_instructions(mut self, enabled: bool) -> Self {
+        self.project_instructions = enabled;
+        self
+    }
+
+    /// Natural language for answers (e.g. "German"). Code and identifiers
+    /// are left alone.
+    pub fn language(mut self, language: Option<String>) -> Self {
+        self.language = language;
+        self
+    }
+
+    /// Post-processing for chat and edit output. Defaults to stripping
+    /// `<think>` blocks. Not applied to fill-in-the-middle completions.
+    pub fn transforms(mut self, transforms: Vec<Transform>) -> Self {
+        self.transforms = Some(transforms);
+        self
+    }
+
+    /// Sampling seed for every request, so runs can be reproduced
+    /// exactly (with the same model, options and hardware).
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Context window for every request, in tokens. Ollama otherwise uses
+    /// its default, which is often smaller than the model supports.
+    pub fn num_ctx(mut self, num_ctx: Option<u32>) -> Self {
+        self.num_ctx = num_ctx;
+        self
+    }
+
+    /// Keep reasoning in the answer (inside `<think>` tags) instead of
+    /// moving it to [`ResponseMetadata::reasoning`].
+    pub fn show_thinking(mut self, show: bool) -> Self {
+        self.show_thinking = show;
+        self
+    }
+
+    pub fn build(self) -> AiCoder {
+        let mut config = config::resolve_config(self.model, self.host, None, None);
+        if let Some(transforms) = self.transforms {
+            config.transforms = transforms;
+        }
+        if self.show_thinking {
+            config
+                .transforms
+                .retain(|transform| *transform != Transform::StripThinking);
+        }
+        config.seed = self.seed;
+        config.num_ctx = self.num_ctx;
+        for host in self.endpoints {
+            if !config.hosts.contains(&host) {
+                config.hosts.push(host);
+            }
+        }
+        AiCoder {
+            balancer: Arc::new(Balancer::new(config.hosts.clone())),
+            client: self.client.unwrap_or_default(),
+            config,
+            system_prompt: self.system_prompt,
+            project_instructions: self.project_instructions,
+            language: self.language,
+            show_thinking: self.show_thinking,
+        }
+    }
+}
+
+impl AiCoder {
+    pub fn builder() -> AiCoderBuilder {
+        AiCoderBuilder::default()
+    }
+
+    pub fn config(&self) -> &EffectiveConfig {
+        &self.config
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The explicit system prompt, or project instructions discovered from
+    /// `dir`, followed by the answer language if one is set.
+    pub fn system_prompt_for(&self, dir: &Path) -> Result<Option<String>, RuntimeError> {
+        let base = if self.system_prompt.is_some() || !self.project_instructions {
+            self.system_prompt.clone()
+        } else {
+            let global = instructions::global_instructions_path();
+            let files = instructions::load(&instructions::discover(dir, global.as_deref()))?;
+            instructions::system_prompt(&files)
+        };
+        let Some(language) = &self.language else {
+            return Ok(base);
+        };
+        let directive = format!(
+            "Write all explanations and prose in {language}. Keep code, identifiers, and command names unchanged."
+        );
+        Ok(Some(match base {
+            Some(base) => format!("{base}\n\n{directive}"),
+            None => directive,
+        }))
+    }
+
+    fn chat_request(&self, prompt: String, stream: bool) -> Result<GenerateRequest, RuntimeError> {
+        Ok(GenerateRequest {
+            model: self.config.model.clone(),
+            prompt,
+            system: self.system_prompt_for(&std::env::current_dir()?)?,
+            stream,
+            ..Default::default()
+        })
+        .map(|request| self.with_defaults(&request))
+    }
+
+    /// `request` with the configured seed and context window, unless it
+    /// sets its own.
+    fn with_defaults(&self, request: &GenerateRequest) -> GenerateRequest {
+        let mut request = request.clone();
+        if let Some(seed) = self.config.seed {
+            let options = request.options.get_or_insert_with(Default::default);
+            options.seed = options.seed.or(Some(seed));
+        }
+        if let Some(num_ctx) = self.config.num_ctx {
+            let options = request.options.get_or_insert_with(Default::default);
+            options.num_ctx = options.num_ctx.or(Some(num_ctx));
+        }
+        request
+    }
+
+    /// Sends a prompt and returns the whole answer.
+    pub async fn chat(&self, prompt: impl Into<String>) -> Result<Completion, RuntimeError> {
+        let request = self.chat_request(prompt.into(), false)?;
+        self.generate(&request).await
+    }
+
+    /// Sends a prompt and streams the answer into `out` as it is generated.
+    pub async fn chat_streaming<W: Write>(
+        &self,
+        prompt: impl Into<String>,
+        out: &mut W,
+    ) -> Result<Completion, RuntimeError> {
+        self.chat_streaming_with_images(prompt, Vec::new(), out)
+            .await
+    }
+
+    /// Like [`AiCoder::chat_streaming`], attaching base64-encoded images
+    /// (see [`ollama::load_image`]) for vision models.
+    pub async fn chat_streaming_with_images<W: Write>(
+        &self,
+        prompt: impl Into<String>,
+        images: Vec<String>,
+        out: &mut W,
+    ) -> Result<Completion, RuntimeError> {
+        let mut request = self.chat_request(prompt.into(), true)?;
+        request.images = images;
+        let (host, show) = (&self.config.host, self.show_thinking);
+        let completion = if self.config.transforms.is_empty() {
+            ollama::generate(&self.client, host, &request, out, show).await?
+        } else {
+            let mut writer = TransformWriter::new(out, &self.config.transforms);
+            let completion =
+                ollama::generate(&self.client, host, &request, &mut writer, show).await?;
+            writer.finish()?;
+            completion
+        };
+        Ok(self.postprocess(completion))
+    }
+
+    /// What the configured model supports.
+    pub async fn capabilities(&self) -> Result<Capabilities, RuntimeError> {
+        ollama::show_capabilities(&self.client, &self.config.host, &self.config.model).await
+    }
+
+    /// Fails early if the model is known to lack `capability`. If the
+    /// server can't say, the request itself will report any problem.
+    pub async fn require(&self, capability: Capability) -> Result<(), RuntimeError> {
+        match self.capabilities().await {
+            Ok(capabilities) if !capabilities.supports(capability) => {
+                Err(RuntimeError::Unsupported {
+                    model: self.config.model.clone(),
+                    capability,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs an arbitrary request against the configured host, applying the
+    /// configured transforms to the answer.
+    /// With several endpoints, a server that can't be reached or fails is
+    /// set aside for a while and the request is retried on another.
+    pub async fn generate(&self, request: &GenerateRequest) -> Result<Completion, RuntimeError> {
+        let request = &self.with_defaults(request);
+        let mut tried = Vec::new();
+        while let Some(lease) = self.balancer.acquire(&tried) {
+            let err = match ollama::complete(&self.client, lease.host(), request).await {
+                Ok(completion) => return Ok(self.postprocess(completion)),
+                Err(err) => err,
+            };
+            if !err.is_retryable() || self.balancer.len() == 1 {
+                return Err(err);
+            }
+            self.balancer.eject(lease.index);
+            tried.push(lease.index);
+            if tried.len() == self.balancer.len() {
+                return Err(err);
+            }
+            eprintln!(
+                "[ai-coder] warning: {} failed ({err}); trying another endpoint",
+                lease.host()
+            );
+        }
+        Err(RuntimeError::Usage("no Ollama host configured".to_string()))
+    }
+
+    /// Waits up to `limit` for any configured host to answer again.
+    pub async fn wait_until_ready(&self, limit: std::time::Duration) -> bool {
+        let checks = self.config.hosts.iter().map(|host| {
+            Box::pin(async move {
+                if ollama::wait_until_ready(&self.client, host, limit).await {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            })
+        });
+        futures_util::future::select_ok(checks).await.is_ok()
+    }
+
+    /// Applies the transforms to a whole answer, moving stripped reasoning
+    /// into the metadata.
+    fn postprocess(&self, mut completion: Completion) -> Completion {
+        let mut pipeline = Pipeline::new(&self.config.transforms);
+        let mut text = pipeline.push(&completion.text);
+        text.push_str(&pipeline.finish());
+        completion.text = text;
+        if completion.metadata.reasoning.is_none() {
+            completion.metadata.reasoning = pipeline.take_reasoning();
+        }
+        completion
+    }
+
+    /// Fill-in-the-middle completion between `prefix` and `suffix`.
+    pub async fn complete_fim(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        options: &FimOptions,
+    ) -> Result<Completion, RuntimeError> {
+        fim::complete_fim(&self.client, &self.config, prefix, suffix, options).await
+    }
+
+    /// Rewrites a selection (or completes at a cursor) as described by `request`.
+    pub async fn edit(&self, request: ActionRequest) -> Result<ActionResponse, RuntimeError> {
+        action::handle(self, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AiCoder, GenerateRequest, GenerationOptions};
+
+    #[test]
+    fn builder_falls_back_to_default_host_and_model() {
+        let coder = AiCoder::builder().model("deepseek-coder-v2").build();
+
+        assert_eq!(coder.config().model, "deepseek-coder-v2");
+        assert_eq!(coder.config().host, "http://localhost:11434");
+    }
+
+    #[test]
+    fn explicit_system_prompt_wins_over_project_instructions() {
+        let coder = AiCoder::builder()
+            .system_prompt(Some("Answer in one line.".to_string()))
+            .project_instructions(true)
+            .build();
+
+        assert_eq!(
+            coder.system_prompt_for(std::path::Path::new(".")).unwrap(),
+            Some("Answer in one line.".to_string())
+        );
+    }
+
+    #[test]
+    fn language_directive_is_appended_to_the_system_prompt() {
+        let dir = std::path::Path::new(".");
+        let coder = AiCoder::builder()
+            .system_prompt(Some("Be brief.".to_string()))
+            .language(Some("German".to_string()))
+            .build();
+
+        assert_eq!(
+            coder.system_prompt_for(dir).unwrap().as_deref(),
+            Some("Be brief.\n\nWrite all explanations and prose in German. Keep code, identifiers, and command names unchanged.")
+        );
+        assert_eq!(
+            AiCoder::builder().build().system_prompt_for(dir).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn seed_is_added_to_requests_without_one() {
+        let coder = AiCoder::builder().seed(Some(42)).build();
+        let seed = |request: &GenerateRequest| request.options.as_ref().and_then(|o| o.seed);
+
+        assert_eq!(
+            seed(&coder.chat_request("hi".to_string(), true).unwrap()),
+            Some(42)
+        );
+
+        let own = GenerateRequest {
+            options: Some(GenerationOptions {
+                seed: Some(7),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(seed(&coder.with_defaults(&own)), Some(7));
+        assert_eq!(
+            AiCoder::builder()
+                .build()
+                .with_defaults(&GenerateRequest::default())
+                .options,
+            None
+        );
+    }
+
+    #[test]
+    fn configured_context_window_is_sent_with_requests() {
+        let coder = AiCoder::builder().num_ctx(Some(16384)).build();
+        let request = coder.chat_request("hi".to_string(), true).unwrap();
+
+        assert_eq!(request.options.and_then(|o| o.num_ctx), Some(16384));
+    }
+}
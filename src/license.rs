@@ -0,0 +1,299 @@
+use crate::action;
+use crate::error::RuntimeError;
+use crate::AiCoder;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Licenses accepted by `license deps` when no `--allow` is given: the
+/// common permissive ones, compatible with distributing under MIT.
+pub const DEFAULT_ALLOWED: [&str; 11] = [
+    "MIT",
+    "MIT-0",
+    "Apache-2.0",
+    "BSD-2-Clause",
+    "BSD-3-Clause",
+    "ISC",
+    "Zlib",
+    "0BSD",
+    "CC0-1.0",
+    "Unlicense",
+    "Unicode-3.0",
+];
+
+/// How much of a license file the model is shown to identify it.
+const MAX_LICENSE_TEXT: usize = 4_000;
+
+/// `header` as line comments in `file`'s language, or `None` for
+/// languages without a known comment syntax.
+pub fn render_header(header: &str, file: &Path) -> Option<String> {
+    let prefix = match action::language_for(file)? {
+        "python" | "ruby" | "bash" => "#",
+        "lua" => "--",
+        _ => "//",
+    };
+    let lines = header.trim().lines().map(|line| match line.trim_end() {
+        "" => prefix.to_string(),
+        line => format!("{prefix} {line}"),
+    });
+    Some(lines.map(|line| line + "\n").collect())
+}
+
+/// Where a header goes: after a `#!` line, if any.
+fn header_offset(contents: &str) -> usize {
+    if contents.starts_with("#!") {
+        contents.find('\n').map_or(contents.len(), |end| end + 1)
+    } else {
+        0
+    }
+}
+
+pub fn has_header(contents: &str, header: &str) -> bool {
+    contents[header_offset(contents)..]
+        .trim_start()
+        .starts_with(header.trim_end())
+}
+
+pub fn insert_header(contents: &str, header: &str) -> String {
+    let (shebang, rest) = contents.split_at(header_offset(contents));
+    format!("{shebang}{header}\n{rest}")
+}
+
+/// Checks every source file matching `pattern` for `header` (plain text,
+/// without comment markers), adding it to files that lack it with `fix`.
+/// Returns the files that were missing it.
+pub fn check_headers(header: &str, pattern: &str, fix: bool) -> Result<Vec<PathBuf>, RuntimeError> {
+    let mut missing = Vec::new();
+    for file in crate::batch::matching_files(pattern)? {
+        // Files in other languages are out of scope, not failures.
+        let Some(rendered) = render_header(header, &file) else {
+            continue;
+        };
+        let contents = fs::read_to_string(&file)?;
+        if has_header(&contents, &rendered) {
+            continue;
+        }
+        if fix {
+            fs::write(&file, insert_header(&contents, &rendered))?;
+        }
+        missing.push(file);
+    }
+    Ok(missing)
+}
+
+/// Whether an SPDX expression such as `MIT OR Apache-2.0` or
+/// `(MIT AND Zlib) OR Apache-2.0 WITH LLVM-exception` can be satisfied by
+/// `allowed`. Exceptions are ignored, and the legacy `MIT/Apache-2.0` form
+/// means OR.
+pub fn satisfies(expression: &str, allowed: &[String]) -> bool {
+    let spaced = expression
+        .replace('/', " OR ")
+        .replace('(', " ( ")
+        .replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut position = 0;
+    let result = or_expression(&tokens, &mut position, allowed);
+    result && position == tokens.len()
+}
+
+fn or_expression(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    let mut any = and_expression(tokens, position, allowed);
+    while tokens.get(*position) == Some(&"OR") {
+        *position += 1;
+        // Evaluated unconditionally so the whole expression is consumed.
+        any |= and_expression(tokens, position, allowed);
+    }
+    any
+}
+
+fn and_expression(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    let mut all = license(tokens, position, allowed);
+    while tokens.get(*position) == Some(&"AND") {
+        *position += 1;
+        all &= license(tokens, position, allowed);
+    }
+    all
+}
+
+fn license(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    let Some(&token) = tokens.get(*position) else {
+        return false;
+    };
+    *position += 1;
+    if token == "(" {
+        let inner = or_expression(tokens, position, allowed);
+        let closed = tokens.get(*position) == Some(&")");
+        *position += 1;
+        return inner && closed;
+    }
+    if tokens.get(*position) == Some(&"WITH") {
+        *position += 2;
+    }
+    allowed.iter().any(|id| id.eq_ignore_ascii_case(token))
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+    license: Option<String>,
+    license_file: Option<String>,
+    manifest_path: PathBuf,
+}
+
+/// A dependency whose license isn't in the allowed list, or was only
+/// identified by the model and so needs human review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub package: String,
+    /// The SPDX expression, empty when none is declared.
+    pub license: String,
+    /// Whether the model read it from the package's license file.
+    pub identified: bool,
+    /// Whether `license` satisfies the allowed list.
+    pub allowed: bool,
+}
+
+impl Finding {
+    /// Whether this finding fails the check. A model-identified license
+    /// is a guess, so it only passes when `trust_identified` is set.
+    pub fn fails(&self, trust_identified: bool) -> bool {
+        !self.allowed || (self.identified && !trust_identified)
+    }
+}
+
+/// The model's guess at the SPDX identifier of a license text.
+async fn identify(coder: &AiCoder, text: &str) -> Result<String, RuntimeError> {
+    let text: String = text.chars().take(MAX_LICENSE_TEXT).collect();
+    let completion = coder
+        .chat(format!(
+            "Which license is this? Reply with only its SPDX expression \
+             (e.g. `MIT` or `MIT OR Apache-2.0`), or `UNKNOWN` if it is not a standard license.\n\n\
+             ```text\n{text}\n```\n"
+        ))
+        .await?;
+    Ok(completion.text.trim().trim_matches('`').to_string())
+}
+
+/// Checks the licenses of every package in the Cargo dependency graph
+/// against `allowed`. Packages that only ship a license file are
+/// identified by the model and always reported; the project's own crates
+/// are skipped.
+pub async fn check_dependencies(
+    coder: &AiCoder,
+    allowed: &[String],
+) -> Result<Vec<Finding>, RuntimeError> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1"])
+        .output()?;
+    if !output.status.success() {
+        return Err(RuntimeError::Usage(format!(
+            "`cargo metadata` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)
+        .map_err(|err| RuntimeError::Usage(format!("unexpected `cargo metadata` output: {err}")))?;
+
+    let mut findings = Vec::new();
+    for package in metadata.packages {
+        if package.manifest_path.starts_with(&metadata.workspace_root) {
+            continue;
+        }
+        let name = format!("{} {}", package.name, package.version);
+        let (license, identified) = match (package.license, package.license_file) {
+            (Some(license), _) => (license, false),
+            (None, Some(file)) => {
+                let dir = package.manifest_path.parent().unwrap_or(Path::new("."));
+                let text = fs::read_to_string(dir.join(file))?;
+                eprintln!("[ai-coder] Identifying the license file of {name}");
+                (identify(coder, &text).await?, true)
+            }
+            (None, None) => (String::new(), false),
+        };
+        let is_allowed = satisfies(&license, allowed);
+        if !is_allowed || identified {
+            findings.push(Finding {
+                package: name,
+                license,
+                identified,
+                allowed: is_allowed,
+            });
+        }
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_header, insert_header, render_header, satisfies, Finding, DEFAULT_ALLOWED};
+    use std::path::Path;
+
+    fn allowed() -> Vec<String> {
+        DEFAULT_ALLOWED.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn evaluates_spdx_expressions() {
+        let allowed = allowed();
+
+        assert!(satisfies("MIT OR Apache-2.0", &allowed));
+        assert!(satisfies("MIT/Apache-2.0", &allowed));
+        assert!(satisfies("Apache-2.0 WITH LLVM-exception", &allowed));
+        assert!(satisfies("(MIT AND Zlib) OR GPL-3.0", &allowed));
+        assert!(!satisfies("MIT AND GPL-3.0", &allowed));
+        assert!(!satisfies("LGPL-2.1-or-later", &allowed));
+        assert!(!satisfies("(MIT", &allowed));
+        assert!(!satisfies("", &allowed));
+    }
+
+    #[test]
+    fn identified_licenses_fail_unless_trusted() {
+        let finding = |identified, allowed| Finding {
+            package: "left-pad 1.0.0".to_string(),
+            license: "MIT".to_string(),
+            identified,
+            allowed,
+        };
+
+        assert!(finding(true, true).fails(false));
+        assert!(!finding(true, true).fails(true));
+        assert!(finding(true, false).fails(true));
+        assert!(finding(false, false).fails(true));
+    }
+
+    #[test]
+    fn headers_use_the_languages_comment_syntax() {
+        let header = "Copyright Acme\n\nSPDX-License-Identifier: MIT\n";
+
+        assert_eq!(
+            render_header(header, Path::new("src/lib.rs")).unwrap(),
+            "// Copyright Acme\n//\n// SPDX-License-Identifier: MIT\n"
+        );
+        assert!(render_header(header, Path::new("run.py"))
+            .unwrap()
+            .starts_with("# Copyright"));
+        assert_eq!(render_header(header, Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn headers_go_after_a_shebang() {
+        let header = "# SPDX-License-Identifier: MIT\n";
+        let script = "#!/bin/sh\necho hi\n";
+
+        assert!(!has_header(script, header));
+        let fixed = insert_header(script, header);
+        assert_eq!(
+            fixed,
+            "#!/bin/sh\n# SPDX-License-Identifier: MIT\n\necho hi\n"
+        );
+        assert!(has_header(&fixed, header));
+    }
+}
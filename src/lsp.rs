@@ -0,0 +1,528 @@
+use crate::action::{
+    self, ActionKind, ActionRequest, Position, PositionEncoding, Range, SurroundingContext,
+};
+use crate::error::RuntimeError;
+use crate::fim::FimOptions;
+use crate::ollama::GenerateRequest;
+use crate::AiCoder;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+const EXPLAIN: &str = "ai-coder.explain";
+const FIX_DIAGNOSTIC: &str = "ai-coder.fixDiagnostic";
+const GENERATE_TEST: &str = "ai-coder.generateTest";
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// `window/showMessage` types.
+const MESSAGE_ERROR: u8 = 1;
+const MESSAGE_INFO: u8 = 3;
+
+/// Reads one `Content-Length`-framed JSON-RPC message. Returns `None` at EOF.
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = content_length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn encode_message(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    char::from(byte).to_digit(16).map(|digit| digit as u8)
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let bytes = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // A `%` not followed by two hex digits is kept as written.
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+                decoded.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// The position encoding to use: `utf-32` if the client offers it,
+/// otherwise LSP's default `utf-16`.
+fn position_encoding(params: &Value) -> PositionEncoding {
+    let offered = params["capabilities"]["general"]["positionEncodings"]
+        .as_array()
+        .is_some_and(|encodings| encodings.iter().any(|encoding| encoding == "utf-32"));
+    if offered {
+        PositionEncoding::Utf32
+    } else {
+        PositionEncoding::Utf16
+    }
+}
+
+/// Code actions offered for a selection: explain and generate-test always,
+/// plus one fix per diagnostic in range.
+fn code_actions(params: &Value) -> Vec<Value> {
+    let uri = &params["textDocument"]["uri"];
+    let range = &params["range"];
+    let command = |title: String, kind: &str, name: &str, arguments: Value| {
+        json!({
+            "title": title,
+            "kind": kind,
+            "command": { "title": title, "command": name, "arguments": arguments },
+        })
+    };
+
+    let mut actions = vec![command(
+        "ai-coder: Explain selection".to_string(),
+        "refactor",
+        EXPLAIN,
+        json!([uri, range]),
+    )];
+    if let Some(diagnostics) = params["context"]["diagnostics"].as_array() {
+        for diagnostic in diagnostics {
+            let message = diagnostic["message"].as_str().unwrap_or_default();
+            actions.push(command(
+                format!(
+                    "ai-coder: Fix \"{}\"",
+                    message.lines().next().unwrap_or_default()
+                ),
+                "quickfix",
+                FIX_DIAGNOSTIC,
+                json!([uri, diagnostic["range"], message]),
+            ));
+        }
+    }
+    actions.push(command(
+        "ai-coder: Generate test".to_string(),
+        "refactor",
+        GENERATE_TEST,
+        json!([uri, range]),
+    ));
+    actions
+}
+
+#[derive(Clone)]
+struct Server {
+    coder: AiCoder,
+    /// Open documents by URI (full-text sync).
+    documents: Arc<Mutex<HashMap<String, String>>>,
+    outgoing: mpsc::UnboundedSender<Value>,
+    next_request_id: Arc<AtomicU64>,
+    /// Agreed with the client in `initialize`.
+    encoding: Arc<Mutex<PositionEncoding>>,
+}
+
+impl Server {
+    fn send(&self, message: Value) {
+        let _ = self.outgoing.send(message);
+    }
+
+    fn respond(&self, id: &Value, result: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    }
+
+    fn respond_error(&self, id: &Value, code: i64, message: &str) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }));
+    }
+
+    fn show_message(&self, kind: u8, message: &str) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "window/showMessage",
+            "params": { "type": kind, "message": message },
+        }));
+    }
+
+    fn apply_edit(&self, label: &str, uri: &str, range: Range, new_text: String) {
+        let id = self.next_request_id.fetch_add(1, Ordering::SeqCst);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": format!("ai-coder-{id}"),
+            "method": "workspace/applyEdit",
+            "params": {
+                "label": label,
+                "edit": { "changes": { uri: [{ "range": range, "newText": new_text }] } },
+            },
+        }));
+    }
+
+    fn encoding(&self) -> PositionEncoding {
+        *self.encoding.lock().unwrap()
+    }
+
+    fn document_text(&self, uri: &str) -> Result<String, RuntimeError> {
+        if let Some(text) = self.documents.lock().unwrap().get(uri) {
+            return Ok(text.clone());
+        }
+        let path = uri_to_path(uri)
+            .ok_or_else(|| RuntimeError::Usage(format!("unsupported document URI {uri}")))?;
+        Ok(fs::read_to_string(path)?)
+    }
+
+    fn handle_notification(&self, method: &str, params: &Value) {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                if let Some(text) = params["textDocument"]["text"].as_str() {
+                    self.documents
+                        .lock()
+                        .unwrap()
+                        .insert(uri.to_string(), text.to_string());
+                }
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let Some(text) = text {
+                    self.documents
+                        .lock()
+                        .unwrap()
+                        .insert(uri.to_string(), text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.lock().unwrap().remove(uri);
+            }
+            _ => {}
+        }
+    }
+
+    async fn execute_command(
+        &self,
+        command: &str,
+        arguments: &[Value],
+    ) -> Result<(), RuntimeError> {
+        let invalid = || RuntimeError::Usage(format!("invalid arguments for {command}"));
+        let uri = arguments
+            .first()
+            .and_then(Value::as_str)
+            .ok_or_else(invalid)?;
+        let range: Range = arguments
+            .get(1)
+            .cloned()
+            .and_then(|range| serde_json::from_value(range).ok())
+            .ok_or_else(invalid)?;
+        let path = uri_to_path(uri).unwrap_or_else(|| PathBuf::from(uri));
+
+        let text = self.document_text(uri)?;
+        let (before, selection, after) =
+            action::split_at_range(&text, range, action::CONTEXT_LINES, self.encoding())
+                .ok_or_else(|| {
+                    RuntimeError::Usage("range is outside of the document".to_string())
+                })?;
+
+        match command {
+            FIX_DIAGNOSTIC => {
+                let message = arguments.get(2).and_then(Value::as_str).unwrap_or_default();
+                let request = ActionRequest {
+                    id: None,
+                    kind: ActionKind::Edit,
+                    file: path,
+                    range,
+                    instruction: format!("Fix this diagnostic: {message}"),
+                    selection: Some(selection),
+                    context: Some(SurroundingContext { before, after }),
+                    language: None,
+                    fim_template: None,
+                };
+                let edit = self.coder.edit(request).await?;
+                self.apply_edit("ai-coder: fix diagnostic", uri, range, edit.new_text);
+            }
+            EXPLAIN | GENERATE_TEST => {
+                let language = action::language_for(&path).unwrap_or_default();
+                let task = if command == EXPLAIN {
+                    "Explain what the following code does, including anything surprising. Be concise."
+                } else {
+                    "Write unit tests for the following code. Reply with only the test code in a single fenced code block."
+                };
+                let request = GenerateRequest {
+                    model: self.coder.config().model.clone(),
+                    prompt: format!(
+                        "{task}\n\nFile: {}\n\n```{language}\n{selection}\n```\n",
+                        path.display()
+                    ),
+                    system: action::system_prompt_for(&self.coder, &path)?,
+                    ..Default::default()
+                };
+                let completion = self.coder.generate(&request).await?;
+
+                if command == EXPLAIN {
+                    self.show_message(MESSAGE_INFO, completion.text.trim());
+                } else {
+                    let insert_at = Range {
+                        start: range.end,
+                        end: range.end,
+                    };
+                    let test = action::extract_code(&completion.text);
+                    self.apply_edit(
+                        "ai-coder: generate test",
+                        uri,
+                        insert_at,
+                        format!("\n\n{}\n", test.trim_end()),
+                    );
+                }
+            }
+            _ => return Err(RuntimeError::Usage(format!("unknown command {command}"))),
+        }
+        Ok(())
+    }
+
+    /// Fill-in-the-middle completion at the cursor, offered as a single
+    /// completion item that inserts the generated text.
+    async fn complete(&self, params: &Value) -> Result<Vec<Value>, RuntimeError> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let position: Position = serde_json::from_value(params["position"].clone())
+            .map_err(|err| RuntimeError::Usage(format!("invalid position: {err}")))?;
+        let cursor = Range {
+            start: position,
+            end: position,
+        };
+
+        let text = self.document_text(uri)?;
+        let (prefix, _, suffix) =
+            action::split_at_range(&text, cursor, action::FIM_CONTEXT_LINES, self.encoding())
+                .ok_or_else(|| {
+                    RuntimeError::Usage("cursor is outside of the document".to_string())
+                })?;
+        let completion = self
+            .coder
+            .complete_fim(&prefix, &suffix, &FimOptions::default())
+            .await?;
+
+        if completion.text.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let label = completion.text.trim().lines().next().unwrap_or_default();
+        Ok(vec![json!({
+            "label": label,
+            "kind": 1,
+            "detail": "ai-coder",
+            "textEdit": { "range": cursor, "newText": completion.text },
+        })])
+    }
+
+    /// Handles one request. Returns false once the client sends `exit`.
+    fn dispatch(&self, message: Value) -> bool {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            if method == "exit" {
+                return false;
+            }
+            self.handle_notification(method, params);
+            return true;
+        };
+        if method.is_empty() {
+            // Response to one of our own requests (workspace/applyEdit).
+            return true;
+        }
+
+        match method {
+            "initialize" => {
+                let encoding = position_encoding(params);
+                *self.encoding.lock().unwrap() = encoding;
+                self.respond(
+                    id,
+                    json!({
+                        "capabilities": {
+                            "positionEncoding": match encoding {
+                                PositionEncoding::Utf16 => "utf-16",
+                                PositionEncoding::Utf32 => "utf-32",
+                            },
+                            "textDocumentSync": 1,
+                            "codeActionProvider": true,
+                            "completionProvider": {},
+                            "executeCommandProvider": {
+                                "commands": [EXPLAIN, FIX_DIAGNOSTIC, GENERATE_TEST],
+                            },
+                        },
+                        "serverInfo": { "name": "ai-coder", "version": env!("CARGO_PKG_VERSION") },
+                    }),
+                );
+            }
+            "shutdown" => self.respond(id, Value::Null),
+            "textDocument/codeAction" => self.respond(id, json!(code_actions(params))),
+            "textDocument/completion" => {
+                let server = self.clone();
+                let id = id.clone();
+                let params = params.clone();
+                tokio::spawn(async move {
+                    match server.complete(&params).await {
+                        Ok(items) => {
+                            server.respond(&id, json!({ "isIncomplete": false, "items": items }))
+                        }
+                        Err(err) => server.respond_error(&id, REQUEST_FAILED, &err.to_string()),
+                    }
+                });
+            }
+            "workspace/executeCommand" => {
+                let Some(command) = params["command"].as_str() else {
+                    self.respond_error(id, INVALID_PARAMS, "missing command");
+                    return true;
+                };
+                // Answer right away; the result arrives as an edit or message.
+                self.respond(id, Value::Null);
+                let server = self.clone();
+                let command = command.to_string();
+                let arguments = params["arguments"].as_array().cloned().unwrap_or_default();
+                tokio::spawn(async move {
+                    if let Err(err) = server.execute_command(&command, &arguments).await {
+                        let message = match err.user_hint() {
+                            Some(hint) => format!("ai-coder: {err} ({hint})"),
+                            None => format!("ai-coder: {err}"),
+                        };
+                        server.show_message(MESSAGE_ERROR, &message);
+                    }
+                });
+            }
+            _ => self.respond_error(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unsupported method {method}"),
+            ),
+        }
+        true
+    }
+}
+
+/// Serves the Language Server Protocol over stdin/stdout until `exit`.
+pub async fn run(coder: AiCoder) -> Result<(), RuntimeError> {
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = queue.recv().await {
+            if stdout.write_all(&encode_message(&message)).await.is_err() {
+                break;
+            }
+            let _ = stdout.flush().await;
+        }
+    });
+
+    let server = Server {
+        coder,
+        documents: Arc::default(),
+        outgoing,
+        next_request_id: Arc::new(AtomicU64::new(1)),
+        encoding: Arc::new(Mutex::new(PositionEncoding::Utf16)),
+    };
+
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    while let Some(message) = read_message(&mut stdin).await? {
+        if !server.dispatch(message) {
+            break;
+        }
+    }
+
+    drop(server);
+    let _ = writer.await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        code_actions, encode_message, position_encoding, read_message, uri_to_path, FIX_DIAGNOSTIC,
+    };
+    use crate::action::PositionEncoding;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn reads_back_encoded_messages() {
+        let first = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize" });
+        let second = json!({ "jsonrpc": "2.0", "method": "exit" });
+        let mut bytes = encode_message(&first);
+        bytes.extend(encode_message(&second));
+
+        let mut reader = &bytes[..];
+
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(first));
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(second));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+    }
+
+    #[test]
+    fn offers_a_fix_per_diagnostic() {
+        let params = json!({
+            "textDocument": { "uri": "file:///src/main.rs" },
+            "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } },
+            "context": { "diagnostics": [
+                { "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 5 } },
+                  "message": "unused variable: `x`" },
+            ] },
+        });
+
+        let actions = code_actions(&params);
+
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[1]["command"]["command"], FIX_DIAGNOSTIC);
+        assert_eq!(
+            actions[1]["title"],
+            "ai-coder: Fix \"unused variable: `x`\""
+        );
+    }
+
+    #[test]
+    fn decodes_file_uris() {
+        assert_eq!(
+            uri_to_path("file:///home/me/my%20project/lib.rs"),
+            Some(PathBuf::from("/home/me/my project/lib.rs"))
+        );
+        assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+        assert_eq!(
+            uri_to_path("file:///tmp/%aé.rs"),
+            Some(PathBuf::from("/tmp/%aé.rs"))
+        );
+    }
+
+    #[test]
+    fn uses_utf32_positions_only_when_offered() {
+        let offered = json!({ "capabilities": { "general": {
+            "positionEncodings": ["utf-8", "utf-32", "utf-16"],
+        } } });
+
+        assert_eq!(position_encoding(&offered), PositionEncoding::Utf32);
+        assert_eq!(position_encoding(&json!({})), PositionEncoding::Utf16);
+    }
+}
@@ -1,186 +1,1179 @@
-use clap::Parser;
-use futures_util::StreamExt;
-use reqwest::Client;
-use serde::Deserialize;
-use serde_json::json;
+use ai_coder::batch::{self, BatchOptions};
+use ai_coder::config::{self, EffectiveConfig};
+use ai_coder::dataset::{FinetuneFormat, Rating};
+use ai_coder::report::ReportFormat;
+use ai_coder::verify::{self, Confidence, Verdict};
+use ai_coder::{
+    action, bench, conflict, dataset, hooks, init, instructions, license, lsp, modelfile, needle,
+    ollama, output, prompts, release, report, tunnel, AiCoder, AiCoderBuilder, Capability,
+    GenerateRequest, RuntimeError,
+};
+use clap::{Parser, Subcommand};
+use futures_util::future::join_all;
+use std::collections::BTreeMap;
 use std::env;
-use std::fs;
-use std::io::{self, Write};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(
     name = "ai-coder",
     version = "0.1.0",
     about = "Local GPU-Accelerated AI Coding CLI",
-    long_about = "A blazingly fast CLI tool for AI-assisted coding using local Ollama models on your GPU"
+    long_about = "A blazingly fast CLI tool for AI-assisted coding using local Ollama models on your GPU",
+    subcommand_negates_reqs = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The coding prompt or question
-    prompt: String,
+    #[arg(required = true)]
+    prompt: Option<String>,
+
+    /// Attach a PNG or JPEG image for vision models, repeatable
+    #[arg(long, value_name = "PATH")]
+    image: Vec<PathBuf>,
 
     /// The model to use
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     model: Option<String>,
 
     /// Ollama host (can also be set via OLLAMA_HOST env var)
-    #[arg(short = 'H', long)]
+    #[arg(short = 'H', long, global = true)]
     host: Option<String>,
 
     /// Optional config file path (default: ./.ai-coder.toml)
-    #[arg(long)]
+    #[arg(long, global = true)]
     config: Option<PathBuf>,
+
+    /// Use the [profiles.NAME] section of the config file
+    #[arg(long, value_name = "NAME", global = true)]
+    config_profile: Option<String>,
+
+    /// Also write the streamed response to this file, followed by a metadata block
+    #[arg(long, visible_alias = "tee", value_name = "PATH", global = true)]
+    save: Option<PathBuf>,
+
+    /// Natural language for answers, e.g. "Japanese" (config: `language`)
+    #[arg(long, global = true)]
+    language: Option<String>,
+
+    /// Fixed sampling seed, for reproducible output
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Show reasoning models' thinking instead of hiding it
+    #[arg(long, global = true)]
+    show_thinking: bool,
+
+    /// Have the model check its answer before showing it and report a confidence level
+    #[arg(long, global = true)]
+    verify: bool,
+
+    /// Ask for a thumbs up or down afterwards, saved to the local feedback dataset
+    #[arg(long, global = true)]
+    rate: bool,
+
+    /// Don't load AGENTS.md / .ai-coder/instructions.md into the system prompt
+    #[arg(long, global = true)]
+    no_instructions: bool,
 }
 
-#[derive(Deserialize, Debug)]
-struct OllamaResponse {
-    response: String,
-    done: bool,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrite a code selection for editor integrations
+    Action {
+        /// Read JSON requests from stdin, one per line, and write JSON edits to stdout
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    /// Run one instruction over many files concurrently, writing a result per file
+    Batch {
+        /// Instruction to apply; may use {{file}}, {{path}} and {{language}}
+        #[arg(long, value_name = "PATH")]
+        prompt_file: PathBuf,
+
+        /// Files to process, e.g. "src/**/*.rs"
+        #[arg(long, value_name = "PATTERN")]
+        glob: String,
+
+        /// Directory for results; the input tree is mirrored inside it
+        #[arg(long, value_name = "DIR")]
+        output_dir: PathBuf,
+
+        /// Requests to run at once
+        #[arg(short, long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+
+        /// Write only the first code block of each reply, under the original file name
+        #[arg(long)]
+        extract_code: bool,
+
+        /// With --extract-code, refuse rewrites that drop more than this percentage of the file's lines
+        #[arg(long = "max-deleted", value_name = "PERCENT", default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+        max_deleted: u8,
+
+        /// With --extract-code, format each rewrite with its language's formatter, retrying once if it doesn't parse
+        #[arg(long, requires = "extract_code")]
+        check_syntax: bool,
+    },
+    /// Send the same prompt to several models at once and show the answers one after another
+    Compare {
+        /// Models to compare, comma-separated
+        #[arg(long, value_delimiter = ',', required = true)]
+        models: Vec<String>,
+
+        /// The prompt to send to each model
+        prompt: String,
+
+        /// Don't record the comparison in the local dataset
+        #[arg(long)]
+        no_record: bool,
+    },
+    /// Work with the local dataset of comparisons and ratings
+    Data {
+        #[command(subcommand)]
+        action: DataCommand,
+    },
+    /// Draft a commit message from the staged changes
+    CommitMessage {
+        /// Put the message at the top of this commit message file instead of printing it
+        #[arg(long, value_name = "PATH")]
+        write: Option<PathBuf>,
+    },
+    /// Review the staged changes and fail if the model finds a problem worth
+    /// stopping the commit for (used by the pre-commit hook)
+    ReviewStaged,
+    /// Manage git hooks
+    Hooks {
+        #[command(subcommand)]
+        action: HooksCommand,
+    },
+    /// Detect a local Ollama server, pick a model, and write the config file
+    Init {
+        /// Accept the suggested server and model without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Run a language server offering AI code actions over stdio
+    Lsp,
+    /// Build and tune models on the server
+    Models {
+        #[command(subcommand)]
+        action: ModelsCommand,
+    },
+    /// Manage and run saved prompt templates
+    Prompt {
+        #[command(subcommand)]
+        action: PromptCommand,
+    },
+    /// Draft release notes from the commits in a git range
+    ReleaseNotes {
+        /// Commit range, e.g. v1.2.0..v1.3.0
+        range: String,
+    },
+    /// Check license headers and dependency licenses
+    License {
+        #[command(subcommand)]
+        action: LicenseCommand,
+    },
+    /// Report TODO comments or unused code, prioritized by the model for planning
+    Report {
+        #[command(subcommand)]
+        kind: ReportCommand,
+    },
+    /// Propose a resolution for each merge conflict and write it once approved
+    ResolveConflicts {
+        /// Write every resolution without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
 }
 
-#[derive(Deserialize, Debug, Default)]
-struct FileConfig {
-    model: Option<String>,
-    host: Option<String>,
+#[derive(Subcommand, Debug)]
+enum PromptCommand {
+    /// Save a named prompt template to the config file
+    Save {
+        /// Name to run the prompt by
+        name: String,
+
+        /// Template text; use {{name}} for variables ({{file}} and {{diff}} are built in)
+        #[arg(required_unless_present = "from_file")]
+        template: Option<String>,
+
+        /// Read the template from a file instead
+        #[arg(long, value_name = "PATH", conflicts_with = "template")]
+        from_file: Option<PathBuf>,
+
+        /// Short description shown by `prompt list`
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Render a saved prompt and send it to the model
+    Run {
+        name: String,
+
+        /// Template variable, repeatable
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+
+        /// File whose contents fill {{file}}
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// List saved prompts
+    List,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct EffectiveConfig {
-    model: String,
-    host: String,
+#[derive(Subcommand, Debug)]
+enum ModelsCommand {
+    /// Create a model from a Modelfile, or from the base model and adapters in its [models.<name>] settings
+    CreateFromModelfile {
+        /// Model to create (default: the configured model)
+        name: Option<String>,
+
+        /// Modelfile to build from instead of the model's settings
+        #[arg(short = 'f', long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// Benchmark the installed variants of a model (e.g. its quantizations) and save the best
+    BenchVariants {
+        /// Model family, optionally with a tag prefix, e.g. qwen2.5-coder or qwen2.5-coder:7b
+        family: String,
+
+        /// Tags to pull and include first, comma-separated, e.g. 7b-instruct-q4_K_M,7b-instruct-q8_0
+        #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+        pull: Vec<String>,
+
+        /// Only report the results; don't write the best variant to the config file
+        #[arg(long)]
+        no_save: bool,
+    },
+    /// Find how much context a model really uses, with needle-in-a-haystack probes, and save it
+    ProbeContext {
+        /// Model to probe
+        name: String,
+
+        /// Largest context window to try, in tokens (default: what the model reports)
+        #[arg(long, value_name = "TOKENS", value_parser = clap::value_parser!(u32).range(2048..))]
+        max: Option<u32>,
+
+        /// Only report the results; don't write num_ctx to the config file
+        #[arg(long)]
+        no_save: bool,
+    },
 }
 
-fn load_file_config(path: &Path) -> Result<FileConfig, Box<dyn std::error::Error>> {
-    let content = fs::read_to_string(path)?;
-    let config: FileConfig = toml::from_str(&content)?;
-    Ok(config)
+#[derive(Subcommand, Debug)]
+enum DataCommand {
+    /// Write the answers rated with --rate as a fine-tuning dataset, with
+    /// secrets redacted. Unrated `compare` results are not exported.
+    ExportFinetune {
+        /// Dataset layout: sharegpt and alpaca include answers rated up; jsonl includes every rating
+        #[arg(long, default_value = "jsonl", value_parser = FinetuneFormat::NAMES)]
+        format: String,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
 }
 
-fn resolve_config(
-    args_model: Option<String>,
-    args_host: Option<String>,
-    env_host: Option<String>,
-    file_config: Option<FileConfig>,
-) -> EffectiveConfig {
-    let file_model = file_config.as_ref().and_then(|config| config.model.clone());
-    let file_host = file_config.and_then(|config| config.host);
+#[derive(Subcommand, Debug)]
+enum HooksCommand {
+    /// Install hooks that draft commit messages and review staged changes
+    Install {
+        /// Replace an existing hook that ai-coder didn't install
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-    let model = args_model
-        .or(file_model)
-        .unwrap_or_else(|| "qwen2.5-coder".to_string());
+#[derive(Subcommand, Debug)]
+enum LicenseCommand {
+    /// List files that don't start with the license header
+    Headers {
+        /// File with the header text, without comment markers
+        #[arg(long, value_name = "PATH")]
+        header: PathBuf,
 
-    let host = args_host
-        .or(env_host)
-        .or(file_host)
-        .unwrap_or_else(|| "http://localhost:11434".to_string());
+        /// Files to check, e.g. "src/**/*.rs"
+        #[arg(long, value_name = "PATTERN")]
+        glob: String,
 
-    EffectiveConfig { model, host }
+        /// Insert the header into files that lack it
+        #[arg(long)]
+        fix: bool,
+    },
+    /// List Cargo dependencies whose license is not allowed
+    Deps {
+        /// Allowed SPDX license, repeatable (default: common permissive licenses)
+        #[arg(long, value_name = "SPDX")]
+        allow: Vec<String>,
+
+        /// Accept licenses the model identified from license files instead
+        /// of failing until someone reviews them
+        #[arg(long)]
+        trust_identified: bool,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    let client = Client::new();
+#[derive(Subcommand, Debug)]
+enum ReportCommand {
+    /// TODO, FIXME, HACK and XXX comments in the files git tracks
+    Todos {
+        /// Print the raw findings as JSON, without asking the model to group
+        /// and rank them
+        #[arg(long, conflicts_with = "format")]
+        raw: bool,
+        /// Layout of the model's prioritized report: markdown, or json
+        /// groups ranked most important first
+        #[arg(long, default_value = "markdown", value_parser = ReportFormat::NAMES)]
+        format: String,
+    },
+    /// Unused code and imports reported by `cargo check`
+    DeadCode {
+        /// Print the raw findings as JSON, without asking the model to group
+        /// and rank them
+        #[arg(long, conflicts_with = "format")]
+        raw: bool,
+        /// Layout of the model's prioritized report: markdown, or json
+        /// groups ranked most important first
+        #[arg(long, default_value = "markdown", value_parser = ReportFormat::NAMES)]
+        format: String,
+    },
+}
 
-    let config_path = args
-        .config
-        .clone()
-        .unwrap_or_else(|| PathBuf::from(".ai-coder.toml"));
+fn parse_var(raw: &str) -> Result<(String, String), String> {
+    raw.split_once('=')
+        .map(|(name, value)| (name.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{raw}`"))
+}
 
-    let file_config = if config_path.exists() {
-        Some(load_file_config(&config_path)?)
-    } else {
-        None
+async fn compare(
+    args: &Args,
+    config: &EffectiveConfig,
+    model_settings: &BTreeMap<String, config::ModelSettings>,
+    models: Vec<String>,
+    prompt: String,
+    record: bool,
+) -> Result<(), RuntimeError> {
+    eprintln!("[ai-coder] Comparing {}", models.join(", "));
+    let mut coders = Vec::new();
+    for model in &models {
+        // Each model gets its own `[models.<name>]` settings, not the default's.
+        let settings = config::model_settings(model_settings, model);
+        let transforms = settings
+            .and_then(|settings| settings.transforms.clone())
+            .unwrap_or_else(|| config::default_transforms(model));
+        coders.push(
+            coder_for(args, config)?
+                .model(model.clone())
+                .transforms(transforms)
+                .num_ctx(settings.and_then(|settings| settings.num_ctx))
+                .project_instructions(!args.no_instructions)
+                .build(),
+        );
+    }
+    let results = join_all(coders.iter().map(|coder| coder.chat(prompt.clone()))).await;
+
+    let mut answers = Vec::new();
+    for (model, result) in models.into_iter().zip(results) {
+        let completion = match result {
+            Ok(completion) => completion,
+            Err(err) => {
+                println!("=== {model}: failed ===\n");
+                report(&err);
+                println!();
+                continue;
+            }
+        };
+        let metadata = &completion.metadata;
+        let mut stats = Vec::new();
+        if let Some(duration) = metadata.total_duration {
+            stats.push(format!("{:.1}s", duration.as_secs_f64()));
+        }
+        if let Some(speed) = metadata.tokens_per_second() {
+            stats.push(format!("{speed:.1} tok/s"));
+        }
+        let stats = if stats.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", stats.join(", "))
+        };
+        println!("=== {model}{stats} ===\n{}\n", completion.text.trim_end());
+        answers.push(dataset::Answer {
+            model,
+            completion_tokens: metadata.completion_tokens,
+            duration_ms: metadata
+                .total_duration
+                .map(|duration| duration.as_millis() as u64),
+            text: completion.text,
+        });
+    }
+    if answers.is_empty() {
+        return Err(RuntimeError::Usage("every model failed".to_string()));
+    }
+    if record {
+        let dir = data_dir()?;
+        let comparison = dataset::Comparison {
+            timestamp: dataset::now(),
+            prompt,
+            answers,
+        };
+        let path = dataset::append(&dir, dataset::COMPARISONS_FILE, &comparison)?;
+        eprintln!("[ai-coder] Recorded the comparison in {}", path.display());
+    }
+    Ok(())
+}
+
+fn report_verdict(verdict: Option<&Verdict>) {
+    let Some(verdict) = verdict else {
+        eprintln!("[ai-coder] warning: the check gave no confidence level; treat the answer as unverified");
+        return;
     };
+    eprintln!("[ai-coder] Confidence: {}", verdict.confidence);
+    for concern in &verdict.concerns {
+        eprintln!("[ai-coder]   - {concern}");
+    }
+    if verdict.confidence == Confidence::Low {
+        eprintln!("[ai-coder] warning: low confidence; verify claims about your code before relying on them");
+    }
+}
 
-    let config = resolve_config(
-        args.model,
-        args.host,
-        env::var("OLLAMA_HOST").ok(),
-        file_config,
-    );
+/// Prints the error, its cause chain, and a remediation hint to stderr.
+fn report(err: &RuntimeError) {
+    eprintln!("\n[ai-coder] error: {err}");
+    let mut source = err.source();
+    while let Some(cause) = source {
+        eprintln!("[ai-coder]   caused by: {cause}");
+        source = cause.source();
+    }
+    if let Some(hint) = err.user_hint() {
+        eprintln!("[ai-coder] hint: {hint}");
+    } else if err.is_retryable() {
+        eprintln!("[ai-coder] hint: this is usually transient; try again");
+    }
+}
 
-    // 2. Construct the full API URL
-    let api_url = format!("{}/api/generate", config.host);
+/// Exit code for a run stopped by a signal, as shells report SIGINT.
+const INTERRUPTED: u8 = 130;
 
-    eprintln!("[ai-coder] Using model: {}", config.model);
-    eprintln!("[ai-coder] Connecting to: {}", config.host);
-    eprintln!("[ai-coder] ---\n");
+/// How long a stopped run gets to wind down before the process exits
+/// anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
 
-    let request_body = json!({
-        "model": config.model,
-        "prompt": args.prompt,
-        "stream": true
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => "interrupted",
+                _ = terminate.recv() => "terminated",
+            };
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    "interrupted"
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let (stop, stopped) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let reason = shutdown_signal().await;
+        let _ = stop.send(reason);
+        // If the run is stuck in a blocking read (e.g. stdin), it never
+        // sees the signal; clean up and exit from here instead.
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+        tunnel::close_all();
+        eprintln!("\n[ai-coder] {reason}");
+        std::process::exit(INTERRUPTED.into());
     });
 
-    // 3. Send the request to Ollama
-    let response = client
-        .post(&api_url)
-        .json(&request_body)
-        .send()
-        .await?;
+    let result = tokio::select! {
+        result = run(Args::parse()) => result,
+        // Dropping the run closes provider connections and the SSH tunnel.
+        Ok(reason) = stopped => {
+            eprintln!("\n[ai-coder] {reason}");
+            return ExitCode::from(INTERRUPTED);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            report(&err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut stream = response.bytes_stream();
+async fn run(mut args: Args) -> Result<(), RuntimeError> {
+    let config_path = args.config.clone().unwrap_or_else(|| {
+        config::config_path(
+            Path::new(config::DEFAULT_CONFIG_PATH),
+            config::user_config_path().as_deref(),
+        )
+    });
+    let (file_config, profile) = config::load_with_profile(
+        &config_path,
+        args.config_profile.as_deref(),
+        &env::current_dir()?,
+    )?;
+    if let Some(name) = &profile {
+        eprintln!("[ai-coder] Using profile: {name}");
+    }
+    let saved_prompts = file_config
+        .as_ref()
+        .map(|config| config.prompts.clone())
+        .unwrap_or_default();
+    let model_settings = file_config
+        .as_ref()
+        .map(|config| config.models.clone())
+        .unwrap_or_default();
 
-    // 4. Stream the output word-by-word to the terminal
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
+    if args.language.is_none() {
+        args.language = file_config.as_ref().and_then(|file| file.language.clone());
+    }
+    let mut config = config::resolve_config(
+        args.model.take(),
+        args.host.take(),
+        env::var("OLLAMA_HOST").ok(),
+        file_config,
+    );
+
+    for (flag, set) in [("--verify", args.verify), ("--rate", args.rate)] {
+        if set && !answers_on_stdout(args.command.as_ref()) {
+            return Err(RuntimeError::Usage(format!(
+                "{flag} only applies to commands that print the model's answer: a plain prompt, \
+                 `prompt run`, `commit-message` without --write, `release-notes` and `report` in markdown"
+            )));
+        }
+    }
 
-        if let Ok(parsed) = serde_json::from_slice::<OllamaResponse>(&chunk) {
-            print!("{}", parsed.response);
-            io::stdout().flush()?; // Ensure immediate rendering
+    let offline = matches!(
+        args.command,
+        Some(Command::Init { .. })
+            | Some(Command::Data { .. })
+            | Some(Command::Hooks { .. })
+            | Some(Command::License {
+                action: LicenseCommand::Headers { .. }
+            })
+            | Some(Command::Report {
+                kind: ReportCommand::Todos { raw: true, .. }
+                    | ReportCommand::DeadCode { raw: true, .. }
+            })
+            | Some(Command::Prompt {
+                action: PromptCommand::Save { .. } | PromptCommand::List
+            })
+    );
+    // Held until the command finishes; dropping it closes the tunnel.
+    let _tunnel = match &config.ssh {
+        Some(destination) if !offline => {
+            eprintln!(
+                "[ai-coder] Opening SSH tunnel to {destination} for {}",
+                config.host
+            );
+            let tunnel = tunnel::open(destination, &config.host).await?;
+            config.host = tunnel.host.clone();
+            config.hosts = vec![tunnel.host.clone()];
+            Some(tunnel)
+        }
+        _ => None,
+    };
 
-            if parsed.done {
-                break;
+    match args.command.take() {
+        Some(Command::Action { .. }) => {
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            action::run_stdio(&coder).await
+        }
+        Some(Command::Batch {
+            prompt_file,
+            glob,
+            output_dir,
+            jobs,
+            extract_code,
+            max_deleted,
+            check_syntax,
+        }) => {
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            let options = BatchOptions {
+                template: fs::read_to_string(prompt_file)?,
+                pattern: glob,
+                output_dir,
+                jobs: jobs.into(),
+                extract_code,
+                max_deleted_percent: max_deleted,
+                check_syntax,
+            };
+            eprintln!("[ai-coder] Using model: {}", config.model);
+            if config.hosts.len() > 1 {
+                eprintln!(
+                    "[ai-coder] Spreading requests across {}",
+                    config.hosts.join(", ")
+                );
+            }
+            batch::run(&coder, &options, |file, result| match result {
+                Ok(destination) => {
+                    eprintln!("[ai-coder] {} -> {}", file.display(), destination.display())
+                }
+                Err(err) => {
+                    eprintln!("[ai-coder] {} failed", file.display());
+                    report(&err);
+                }
+            })
+            .await
+        }
+        Some(Command::Compare {
+            models,
+            prompt,
+            no_record,
+        }) => compare(&args, &config, &model_settings, models, prompt, !no_record).await,
+        Some(Command::Data {
+            action: DataCommand::ExportFinetune { format, output },
+        }) => {
+            let format = FinetuneFormat::from_name(&format).expect("validated by clap");
+            let feedback: Vec<dataset::Feedback> =
+                dataset::read(&data_dir()?, dataset::FEEDBACK_FILE)?;
+            let examples = dataset::export_finetune(&feedback, format);
+            let mut lines = String::new();
+            for example in &examples {
+                lines.push_str(&example.to_string());
+                lines.push('\n');
+            }
+            match &output {
+                Some(path) => fs::write(path, lines)?,
+                None => print!("{lines}"),
+            }
+            eprintln!(
+                "[ai-coder] Exported {} of {} rated answers",
+                examples.len(),
+                feedback.len()
+            );
+            Ok(())
+        }
+        Some(Command::CommitMessage { write }) => {
+            let diff = hooks::staged_diff()?;
+            if diff.trim().is_empty() {
+                return Err(RuntimeError::Usage(
+                    "nothing is staged; `git add` the changes to describe".to_string(),
+                ));
+            }
+            let prompt = hooks::build_prompt(&diff);
+            let Some(path) = write else {
+                return generate_to_stdout(&args, &config, prompt).await;
+            };
+            eprintln!("[ai-coder] Drafting a commit message with {}", config.model);
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            let completion = coder.chat(prompt).await?;
+            let existing = fs::read_to_string(&path).unwrap_or_default();
+            let message = action::extract_code(&completion.text);
+            fs::write(&path, hooks::with_message(&message, &existing))?;
+            Ok(())
+        }
+        Some(Command::ReviewStaged) => {
+            let diff = hooks::staged_diff()?;
+            if diff.trim().is_empty() {
+                return Ok(());
+            }
+            eprintln!(
+                "[ai-coder] Reviewing the staged changes with {}",
+                config.model
+            );
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            // A review that can't run must not stop the commit.
+            let completion = match coder.chat(hooks::build_review_prompt(&diff)).await {
+                Ok(completion) => completion,
+                Err(err) => {
+                    eprintln!("[ai-coder] warning: could not review the staged changes: {err}");
+                    return Ok(());
+                }
+            };
+            let Some(review) = hooks::parse_review(&completion.text) else {
+                eprintln!(
+                    "[ai-coder] warning: the review gave no verdict; not stopping the commit"
+                );
+                return Ok(());
+            };
+            for problem in &review.problems {
+                eprintln!("[ai-coder]   - {problem}");
+            }
+            if !review.block {
+                eprintln!("[ai-coder] Review passed");
+                return Ok(());
+            }
+            Err(RuntimeError::Usage(
+                "the review found problems in the staged changes; fix them, or skip the review \
+                 with `git commit --no-verify`"
+                    .to_string(),
+            ))
+        }
+        Some(Command::Hooks {
+            action: HooksCommand::Install { force },
+        }) => {
+            for path in hooks::install(&env::current_exe()?, force)? {
+                eprintln!("[ai-coder] Installed {}", path.display());
             }
+            Ok(())
+        }
+        Some(Command::Init { yes }) => {
+            init::run(&config.http, &config.host, &config_path, yes).await
+        }
+        Some(Command::Lsp) => {
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            lsp::run(coder).await
+        }
+        Some(Command::Models {
+            action: ModelsCommand::CreateFromModelfile { name, file },
+        }) => {
+            let name = name.unwrap_or_else(|| config.model.clone());
+            let modelfile = match file {
+                Some(path) => modelfile::load(&path)?,
+                None => config::model_settings(&model_settings, &name)
+                    .map(|settings| modelfile::from_settings(settings, config_dir(&config_path)))
+                    .transpose()?
+                    .flatten()
+                    .ok_or_else(|| {
+                        RuntimeError::Usage(format!(
+                            "no Modelfile for {name}; pass -f or set `modelfile` or `base` and `adapters` under [models.\"{name}\"]"
+                        ))
+                    })?,
+            };
+            eprintln!("[ai-coder] Creating {name}");
+            modelfile::create(&config.http.client()?, &config.host, &name, &modelfile).await?;
+            eprintln!("[ai-coder] Created {name}");
+            Ok(())
+        }
+        Some(Command::Models {
+            action:
+                ModelsCommand::BenchVariants {
+                    family,
+                    pull,
+                    no_save,
+                },
+        }) => {
+            bench::run_variants(
+                &config.http.client()?,
+                &config.host,
+                &family,
+                &pull,
+                &config_path,
+                profile.as_deref(),
+                !no_save,
+            )
+            .await
+        }
+        Some(Command::Models {
+            action: ModelsCommand::ProbeContext { name, max, no_save },
+        }) => {
+            needle::run(
+                &config.http.client()?,
+                &config.host,
+                &name,
+                max,
+                &config_path,
+                !no_save,
+            )
+            .await
+        }
+        Some(Command::Prompt { action }) => {
+            run_prompt_command(&args, &config, &config_path, saved_prompts, action).await
+        }
+        Some(Command::ReleaseNotes { range }) => {
+            let commits = release::commits(&range)?;
+            if commits.is_empty() {
+                return Err(RuntimeError::Usage(format!("no commits in {range}")));
+            }
+            eprintln!(
+                "[ai-coder] Summarizing {} commits in {range}",
+                commits.len()
+            );
+            generate_to_stdout(&args, &config, release::build_prompt(&range, &commits)).await
+        }
+        Some(Command::License {
+            action: LicenseCommand::Headers { header, glob, fix },
+        }) => {
+            let missing = license::check_headers(&fs::read_to_string(header)?, &glob, fix)?;
+            for file in &missing {
+                println!("{}", file.display());
+            }
+            match (missing.len(), fix) {
+                (0, _) => Ok(()),
+                (count, true) => {
+                    eprintln!("[ai-coder] Added the header to {count} files");
+                    Ok(())
+                }
+                (count, false) => Err(RuntimeError::Usage(format!(
+                    "{count} files lack the license header; pass --fix to add it"
+                ))),
+            }
+        }
+        Some(Command::License {
+            action:
+                LicenseCommand::Deps {
+                    allow,
+                    trust_identified,
+                },
+        }) => {
+            let allowed = if allow.is_empty() {
+                license::DEFAULT_ALLOWED.map(String::from).to_vec()
+            } else {
+                allow
+            };
+            let coder = coder_for(&args, &config)?.build();
+            let findings = license::check_dependencies(&coder, &allowed).await?;
+            for finding in &findings {
+                let license = match (finding.license.as_str(), finding.identified) {
+                    ("", _) => "no license declared".to_string(),
+                    (license, true) => format!(
+                        "{license} (identified by the model from its license file; needs human review)"
+                    ),
+                    (license, false) => license.to_string(),
+                };
+                println!("{}: {license}", finding.package);
+            }
+            let failed = findings
+                .iter()
+                .filter(|finding| finding.fails(trust_identified))
+                .count();
+            if failed == 0 {
+                eprintln!("[ai-coder] All dependency licenses are allowed");
+                return Ok(());
+            }
+            Err(RuntimeError::Usage(format!(
+                "{failed} dependencies have licenses outside {} or identified by the model; \
+                 review the identified ones and pass --trust-identified to accept them",
+                allowed.join(", ")
+            )))
+        }
+        Some(Command::Report { kind }) => {
+            let (items, subject, raw, format) = match kind {
+                ReportCommand::Todos { raw, format } => {
+                    (report::todos()?, "TODO comments", raw, format)
+                }
+                ReportCommand::DeadCode { raw, format } => {
+                    eprintln!("[ai-coder] Running cargo check...");
+                    (report::dead_code()?, "unused code warnings", raw, format)
+                }
+            };
+            if raw {
+                let items = serde_json::to_string_pretty(&items)
+                    .map_err(|err| RuntimeError::Usage(format!("cannot encode report: {err}")))?;
+                println!("{items}");
+                return Ok(());
+            }
+            if items.is_empty() {
+                eprintln!("[ai-coder] No {subject} found");
+                return Ok(());
+            }
+            eprintln!("[ai-coder] Prioritizing {} {subject}", items.len());
+            let format = ReportFormat::from_name(&format).expect("validated by clap");
+            let prompt = report::build_prompt(subject, &items, format);
+            if format == ReportFormat::Markdown {
+                return generate_to_stdout(&args, &config, prompt).await;
+            }
+            let coder = coder_for(&args, &config)?.build();
+            let completion = coder
+                .generate(&GenerateRequest {
+                    model: config.model.clone(),
+                    prompt,
+                    format: Some(report::json_schema()),
+                    ..Default::default()
+                })
+                .await?;
+            let groups: serde_json::Value =
+                serde_json::from_str(&completion.text).map_err(|err| {
+                    RuntimeError::Usage(format!("the model's report is not valid JSON: {err}"))
+                })?;
+            let groups = serde_json::to_string_pretty(&groups)
+                .map_err(|err| RuntimeError::Usage(format!("cannot encode report: {err}")))?;
+            println!("{groups}");
+            Ok(())
+        }
+        Some(Command::ResolveConflicts { yes }) => {
+            eprintln!("[ai-coder] Using model: {}", config.model);
+            let coder = coder_for(&args, &config)?
+                .project_instructions(!args.no_instructions)
+                .build();
+            conflict::run(&coder, yes).await
+        }
+        None => {
+            let prompt = args.prompt.take().unwrap_or_default();
+            generate_to_stdout(&args, &config, prompt).await
         }
     }
+}
 
-    println!("\n\n[ai-coder] Generation complete");
-    Ok(())
+/// Whether `command` prints the model's answer through
+/// [`generate_to_stdout`], which honors `--verify` and `--rate`.
+fn answers_on_stdout(command: Option<&Command>) -> bool {
+    if let Some(Command::Report {
+        kind: ReportCommand::Todos { raw, format } | ReportCommand::DeadCode { raw, format },
+    }) = command
+    {
+        return !raw && format == "markdown";
+    }
+    matches!(
+        command,
+        None | Some(Command::Prompt {
+            action: PromptCommand::Run { .. }
+        }) | Some(Command::CommitMessage { write: None })
+            | Some(Command::ReleaseNotes { .. })
+    )
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{resolve_config, FileConfig};
-
-    #[test]
-    fn cli_overrides_everything() {
-        let resolved = resolve_config(
-            Some("cli-model".to_string()),
-            Some("http://cli-host:11434".to_string()),
-            Some("http://env-host:11434".to_string()),
-            Some(FileConfig {
-                model: Some("file-model".to_string()),
-                host: Some("http://file-host:11434".to_string()),
-            }),
-        );
+fn coder_for(args: &Args, config: &EffectiveConfig) -> Result<AiCoderBuilder, RuntimeError> {
+    Ok(AiCoder::builder()
+        .client(config.http.client()?)
+        .host(config.host.clone())
+        .endpoints(config.hosts.clone())
+        .model(config.model.clone())
+        .language(args.language.clone())
+        .transforms(config.transforms.clone())
+        .show_thinking(args.show_thinking)
+        .seed(args.seed)
+        .num_ctx(config.num_ctx))
+}
+
+async fn run_prompt_command(
+    args: &Args,
+    config: &EffectiveConfig,
+    config_path: &Path,
+    saved_prompts: BTreeMap<String, prompts::PromptTemplate>,
+    action: PromptCommand,
+) -> Result<(), RuntimeError> {
+    match action {
+        PromptCommand::Save {
+            name,
+            template,
+            from_file,
+            description,
+        } => {
+            let template = match from_file {
+                Some(path) => fs::read_to_string(path)?,
+                None => template.unwrap_or_default(),
+            };
+            prompts::save(config_path, &name, &template, description.as_deref())?;
+            eprintln!(
+                "[ai-coder] Saved prompt '{}' to {}",
+                name,
+                config_path.display()
+            );
+            Ok(())
+        }
+        PromptCommand::List => {
+            if saved_prompts.is_empty() {
+                eprintln!("[ai-coder] No saved prompts in {}", config_path.display());
+            }
+            for (name, prompt) in &saved_prompts {
+                match &prompt.description {
+                    Some(description) => println!("{name}\t{description}"),
+                    None => println!("{name}"),
+                }
+            }
+            Ok(())
+        }
+        PromptCommand::Run { name, vars, file } => {
+            let prompt = saved_prompts.get(&name).ok_or_else(|| {
+                RuntimeError::Usage(format!(
+                    "no saved prompt named '{}' in {}",
+                    name,
+                    config_path.display()
+                ))
+            })?;
 
-        assert_eq!(resolved.model, "cli-model");
-        assert_eq!(resolved.host, "http://cli-host:11434");
+            let mut values: BTreeMap<String, String> = vars.into_iter().collect();
+            for variable in prompts::variables(&prompt.template) {
+                if values.contains_key(&variable) {
+                    continue;
+                }
+                if let Some(builtin) = prompts::builtin_variable(&variable, file.as_deref()) {
+                    values.insert(variable, builtin?);
+                }
+            }
+
+            let rendered = prompts::render(&prompt.template, &values)?;
+            generate_to_stdout(args, config, rendered).await
+        }
     }
+}
 
-    #[test]
-    fn env_host_overrides_file_host() {
-        let resolved = resolve_config(
-            None,
-            None,
-            Some("http://env-host:11434".to_string()),
-            Some(FileConfig {
-                model: Some("file-model".to_string()),
-                host: Some("http://file-host:11434".to_string()),
-            }),
-        );
+/// Sends `prompt` to the model and streams the answer to stdout (and the
+/// `--save` file, if any).
+async fn generate_to_stdout(
+    args: &Args,
+    config: &EffectiveConfig,
+    prompt: String,
+) -> Result<(), RuntimeError> {
+    let images = args
+        .image
+        .iter()
+        .map(|path| ollama::load_image(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let system_prompt = if args.no_instructions {
+        None
+    } else {
+        let global = instructions::global_instructions_path();
+        let paths = instructions::discover(&env::current_dir()?, global.as_deref());
+        let files = instructions::load(&paths)?;
+        for file in &files {
+            eprintln!("[ai-coder] Loaded instructions: {}", file.path.display());
+        }
+        instructions::system_prompt(&files)
+    };
+
+    eprintln!("[ai-coder] Using model: {}", config.model);
+    eprintln!("[ai-coder] Connecting to: {}", config.host);
+    eprintln!("[ai-coder] ---\n");
 
-        assert_eq!(resolved.model, "file-model");
-        assert_eq!(resolved.host, "http://env-host:11434");
+    // Instructions are resolved above so their paths can be reported.
+    let coder = coder_for(args, config)?
+        .system_prompt(system_prompt.clone())
+        .build();
+    if !images.is_empty() {
+        coder.require(Capability::Vision).await?;
     }
 
-    #[test]
-    fn falls_back_to_defaults_without_overrides() {
-        let resolved = resolve_config(None, None, None, None);
+    let question = prompt.clone();
+    let mut file = args.save.as_ref().map(File::create).transpose()?;
+    // A checked answer is held back until the check is done, so a low
+    // rating can be shown above it.
+    let mut held = Vec::new();
+    let completion = if args.verify {
+        eprintln!("[ai-coder] The answer is shown once it has been checked");
+        coder
+            .chat_streaming_with_images(prompt, images, &mut held)
+            .await?
+    } else {
+        match &mut file {
+            Some(file) => {
+                coder
+                    .chat_streaming_with_images(
+                        prompt,
+                        images,
+                        &mut output::Tee::new(io::stdout(), file),
+                    )
+                    .await?
+            }
+            None => {
+                coder
+                    .chat_streaming_with_images(prompt, images, &mut io::stdout())
+                    .await?
+            }
+        }
+    };
 
-        assert_eq!(resolved.model, "qwen2.5-coder");
-        assert_eq!(resolved.host, "http://localhost:11434");
+    let verdict = if args.verify {
+        eprintln!("[ai-coder] Checking the answer...");
+        // A failed check must not cost the answer; it is shown unverified.
+        let verdict = match verify::verify(&coder, &question, &completion.text).await {
+            Ok(verdict) => {
+                report_verdict(verdict.as_ref());
+                verdict
+            }
+            Err(err) => {
+                eprintln!("[ai-coder] warning: unverified: {err}");
+                None
+            }
+        };
+        eprintln!("[ai-coder] ---\n");
+        // Nothing was shown yet, so a restarted answer is shown only once.
+        if completion.restarted {
+            held = completion.text.clone().into_bytes();
+        }
+        let low = verdict.as_ref().filter(|v| v.confidence == Confidence::Low);
+        if let Some(verdict) = low {
+            held.splice(0..0, verify::warning(verdict).into_bytes());
+        }
+        io::stdout().write_all(&held)?;
+        if let Some(file) = &mut file {
+            file.write_all(&held)?;
+        }
+        verdict
+    } else {
+        None
+    };
+    if let (Some(file), Some(path)) = (&mut file, &args.save) {
+        // The file should hold one answer, not a broken one and its retry.
+        // A held-back answer was already cleaned up above.
+        if completion.restarted && !args.verify {
+            output::rewrite(file, &completion.text)?;
+        }
+        file.write_all(output::metadata_footer(&completion.metadata, &config.host).as_bytes())?;
+        if let Some(verdict) = &verdict {
+            file.write_all(verify::footer(verdict).as_bytes())?;
+        }
+        eprintln!("\n[ai-coder] Saved response to {}", path.display());
     }
+
+    println!("\n\n[ai-coder] Generation complete");
+    if args.rate {
+        let Some((rating, note)) = ask_rating()? else {
+            return Ok(());
+        };
+        let feedback = dataset::Feedback {
+            timestamp: dataset::now(),
+            model: config.model.clone(),
+            system: system_prompt,
+            prompt: question,
+            response: completion.text,
+            rating,
+            note,
+        };
+        let path = dataset::append(&data_dir()?, dataset::FEEDBACK_FILE, &feedback)?;
+        eprintln!("[ai-coder] Saved your rating to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Asks for a thumbs up or down and an optional note. `None` if the user
+/// skips, or stdin isn't a terminal to ask on.
+fn ask_rating() -> Result<Option<(Rating, Option<String>)>, RuntimeError> {
+    if !io::stdin().is_terminal() {
+        eprintln!("[ai-coder] warning: --rate needs an interactive terminal; not asking");
+        return Ok(None);
+    }
+    let ask = |question: &str| -> io::Result<String> {
+        eprint!("[ai-coder] {question} ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(answer.trim().to_string())
+    };
+    let Some(rating) = Rating::parse(&ask("Rate this answer [+/-, Enter to skip]:")?) else {
+        return Ok(None);
+    };
+    let note = ask("Note (optional):")?;
+    Ok(Some((rating, (!note.is_empty()).then_some(note))))
+}
+
+/// The directory relative paths in the config file are resolved against.
+fn config_dir(config_path: &Path) -> &Path {
+    match config_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn data_dir() -> Result<PathBuf, RuntimeError> {
+    dataset::data_dir().ok_or_else(|| {
+        RuntimeError::Usage("cannot find a data directory; set XDG_DATA_HOME".to_string())
+    })
 }
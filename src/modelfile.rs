@@ -0,0 +1,341 @@
+use crate::config::ModelSettings;
+use crate::error::RuntimeError;
+use crate::ollama::{self, CreateRequest};
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a model is built on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    /// A model the server already has, e.g. `qwen2.5-coder:7b`.
+    Model(String),
+    /// Local GGUF weights, uploaded before the model is created.
+    Weights(PathBuf),
+}
+
+/// The parts of an Ollama Modelfile that `/api/create` accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modelfile {
+    pub from: Base,
+    /// GGUF LoRA adapters, applied in order.
+    pub adapters: Vec<PathBuf>,
+    pub system: Option<String>,
+    pub template: Option<String>,
+    pub license: Option<String>,
+    pub parameters: Map<String, Value>,
+    pub messages: Vec<Value>,
+}
+
+impl Modelfile {
+    fn new(from: Base) -> Modelfile {
+        Modelfile {
+            from,
+            adapters: Vec::new(),
+            system: None,
+            template: None,
+            license: None,
+            parameters: Map::new(),
+            messages: Vec::new(),
+        }
+    }
+}
+
+/// Whether a `FROM` argument names files rather than a model, following
+/// Ollama: paths start with `.`, `/` or `~`, or end in a weights extension.
+fn is_path(from: &str) -> bool {
+    from.starts_with(['.', '/', '~']) || from.ends_with(".gguf") || from.ends_with(".safetensors")
+}
+
+fn resolve(dir: &Path, path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => dir.join(path),
+    }
+}
+
+/// `"quoted"` values lose their quotes; anything else is kept as written.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Parameter values are sent as JSON numbers or booleans when they look
+/// like one.
+fn parameter_value(value: &str) -> Value {
+    if let Ok(number) = value.parse::<i64>() {
+        return json!(number);
+    }
+    if let Ok(number) = value.parse::<f64>() {
+        return json!(number);
+    }
+    match value {
+        "true" => json!(true),
+        "false" => json!(false),
+        _ => json!(value),
+    }
+}
+
+/// Parses a Modelfile. Relative paths are resolved against `dir`, the
+/// directory the Modelfile is in.
+pub fn parse(text: &str, dir: &Path) -> Result<Modelfile, String> {
+    let mut from = None;
+    let mut modelfile = Modelfile::new(Base::Model(String::new()));
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = index + 1;
+        let (instruction, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut value = rest.trim().to_string();
+        // `"""` values run until the closing `"""`, possibly lines later.
+        if let Some(start) = value.strip_prefix("\"\"\"") {
+            let mut collected = start.to_string();
+            loop {
+                if let Some(end) = collected.find("\"\"\"") {
+                    collected.truncate(end);
+                    break;
+                }
+                let Some((_, next)) = lines.next() else {
+                    return Err(format!("line {number}: unterminated \"\"\""));
+                };
+                collected.push('\n');
+                collected.push_str(next);
+            }
+            value = collected;
+        } else {
+            value = unquote(&value).to_string();
+        }
+        match instruction.to_ascii_uppercase().as_str() {
+            "FROM" if is_path(&value) => from = Some(Base::Weights(resolve(dir, &value))),
+            "FROM" => from = Some(Base::Model(value)),
+            "ADAPTER" => modelfile.adapters.push(resolve(dir, &value)),
+            "SYSTEM" => modelfile.system = Some(value),
+            "TEMPLATE" => modelfile.template = Some(value),
+            "LICENSE" => modelfile.license = Some(value),
+            "PARAMETER" => {
+                let (name, raw) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("line {number}: expected PARAMETER <name> <value>"))?;
+                let raw = unquote(raw.trim());
+                // `stop` may be given several times; Ollama takes a list.
+                if name == "stop" {
+                    let stops = modelfile
+                        .parameters
+                        .entry("stop")
+                        .or_insert_with(|| json!([]));
+                    if let Some(stops) = stops.as_array_mut() {
+                        stops.push(json!(raw));
+                    }
+                } else {
+                    modelfile
+                        .parameters
+                        .insert(name.to_string(), parameter_value(raw));
+                }
+            }
+            "MESSAGE" => {
+                let (role, content) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("line {number}: expected MESSAGE <role> <content>"))?;
+                modelfile
+                    .messages
+                    .push(json!({ "role": role, "content": unquote(content.trim()) }));
+            }
+            other => return Err(format!("line {number}: unsupported instruction {other}")),
+        }
+    }
+    modelfile.from = from.ok_or("no FROM instruction")?;
+    Ok(modelfile)
+}
+
+/// Reads and parses the Modelfile at `path`.
+pub fn load(path: &Path) -> Result<Modelfile, RuntimeError> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        RuntimeError::Usage(format!("cannot read Modelfile {}: {err}", path.display()))
+    })?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    parse(&text, dir).map_err(|err| RuntimeError::Usage(format!("{}: {err}", path.display())))
+}
+
+/// The Modelfile a `[models.<name>]` section describes: its `modelfile`,
+/// or else `base` with `adapters`. `None` if it describes neither.
+/// Relative paths are resolved against `dir`, the config file's directory.
+pub fn from_settings(
+    settings: &ModelSettings,
+    dir: &Path,
+) -> Result<Option<Modelfile>, RuntimeError> {
+    let resolve = |path: &Path| resolve(dir, &path.to_string_lossy());
+    if let Some(path) = &settings.modelfile {
+        return load(&resolve(path)).map(Some);
+    }
+    match &settings.base {
+        Some(base) => {
+            let mut modelfile = Modelfile::new(Base::Model(base.clone()));
+            modelfile.adapters = settings.adapters.iter().map(|path| resolve(path)).collect();
+            Ok(Some(modelfile))
+        }
+        None if settings.adapters.is_empty() => Ok(None),
+        None => Err(RuntimeError::Usage(
+            "`adapters` need a `base` model to apply them to".to_string(),
+        )),
+    }
+}
+
+/// Uploads `path` unless the server has it, returning `(file name, digest)`.
+async fn upload(
+    client: &Client,
+    host: &str,
+    path: &Path,
+) -> Result<(String, String), RuntimeError> {
+    if path.is_dir() {
+        return Err(RuntimeError::Usage(format!(
+            "{} is a directory; convert safetensors weights and adapters to GGUF first",
+            path.display()
+        )));
+    }
+    let size = fs::metadata(path)
+        .map_err(|err| RuntimeError::Usage(format!("cannot read {}: {err}", path.display())))?
+        .len();
+    eprintln!(
+        "[ai-coder] Uploading {} ({} MB)",
+        path.display(),
+        size / 1_000_000
+    );
+    let digest = ollama::push_blob(client, host, path).await?;
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    Ok((name, digest))
+}
+
+/// Creates model `name` on `host` from `modelfile`, uploading local
+/// weights and adapters first.
+pub async fn create(
+    client: &Client,
+    host: &str,
+    name: &str,
+    modelfile: &Modelfile,
+) -> Result<(), RuntimeError> {
+    let mut request = CreateRequest {
+        model: name.to_string(),
+        system: modelfile.system.clone(),
+        template: modelfile.template.clone(),
+        license: modelfile.license.clone(),
+        parameters: modelfile.parameters.clone(),
+        messages: modelfile.messages.clone(),
+        ..CreateRequest::default()
+    };
+    match &modelfile.from {
+        Base::Model(model) => request.from = Some(model.clone()),
+        Base::Weights(path) => {
+            let (file, digest) = upload(client, host, path).await?;
+            request.files.insert(file, digest);
+        }
+    }
+    for adapter in &modelfile.adapters {
+        let (file, digest) = upload(client, host, adapter).await?;
+        request.adapters.insert(file, digest);
+    }
+    ollama::create_model(client, host, &request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_settings, parse, Base};
+    use crate::config::ModelSettings;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parses_adapters_parameters_and_multiline_values() {
+        let text = "# Acme's tuned coder\n\
+                    FROM qwen2.5-coder:7b\n\
+                    ADAPTER ./adapters/acme.gguf\n\
+                    PARAMETER temperature 0.2\n\
+                    PARAMETER num_ctx 8192\n\
+                    PARAMETER stop \"<|im_end|>\"\n\
+                    PARAMETER stop \"<|endoftext|>\"\n\
+                    SYSTEM \"\"\"You review Acme code.\n\
+                    Be brief.\"\"\"\n\
+                    MESSAGE user Hi\n";
+
+        let modelfile = parse(text, Path::new("/work")).unwrap();
+        assert_eq!(modelfile.from, Base::Model("qwen2.5-coder:7b".to_string()));
+        assert_eq!(
+            modelfile.adapters,
+            vec![PathBuf::from("/work/adapters/acme.gguf")]
+        );
+        assert_eq!(
+            serde_json::Value::Object(modelfile.parameters),
+            json!({ "temperature": 0.2, "num_ctx": 8192, "stop": ["<|im_end|>", "<|endoftext|>"] })
+        );
+        assert_eq!(
+            modelfile.system.as_deref(),
+            Some("You review Acme code.\nBe brief.")
+        );
+        assert_eq!(
+            modelfile.messages,
+            vec![json!({ "role": "user", "content": "Hi" })]
+        );
+    }
+
+    #[test]
+    fn from_a_file_means_local_weights() {
+        let modelfile = parse("from weights/model-q4.gguf\n", Path::new("/work")).unwrap();
+
+        assert_eq!(
+            modelfile.from,
+            Base::Weights(PathBuf::from("/work/weights/model-q4.gguf"))
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_modelfiles() {
+        assert_eq!(
+            parse("SYSTEM hi\n", Path::new(".")).unwrap_err(),
+            "no FROM instruction"
+        );
+        assert_eq!(
+            parse("FROM llama3\nSYSTEM \"\"\"open\n", Path::new(".")).unwrap_err(),
+            "line 2: unterminated \"\"\""
+        );
+        assert_eq!(
+            parse("FROM llama3\nQUANTIZE q4\n", Path::new(".")).unwrap_err(),
+            "line 2: unsupported instruction QUANTIZE"
+        );
+    }
+
+    #[test]
+    fn settings_apply_adapters_to_a_base_model() {
+        let settings = ModelSettings {
+            base: Some("qwen2.5-coder:7b".to_string()),
+            adapters: vec![PathBuf::from("adapters/acme.gguf")],
+            ..ModelSettings::default()
+        };
+
+        let dir = Path::new("/work");
+
+        let modelfile = from_settings(&settings, dir).unwrap().unwrap();
+        assert_eq!(modelfile.from, Base::Model("qwen2.5-coder:7b".to_string()));
+        assert_eq!(
+            modelfile.adapters,
+            vec![PathBuf::from("/work/adapters/acme.gguf")]
+        );
+        assert!(from_settings(&ModelSettings::default(), dir)
+            .unwrap()
+            .is_none());
+        assert!(from_settings(
+            &ModelSettings {
+                adapters: settings.adapters.clone(),
+                ..ModelSettings::default()
+            },
+            dir
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::config;
+use crate::error::RuntimeError;
+use crate::ollama::{self, GenerateRequest, GenerationOptions};
+use reqwest::Client;
+use std::path::Path;
+
+/// Where in the filler the needle is hidden, as a percentage of its length.
+pub const DEPTHS: [u8; 3] = [10, 50, 90];
+
+/// The smallest context window probed; sizes double from here.
+const FIRST_SIZE: u32 = 2048;
+
+/// Largest window probed when the model doesn't report its own.
+pub const DEFAULT_MAX: u32 = 32_768;
+
+/// Share of the window the prompt fills, leaving room for the answer.
+const FILL: f64 = 0.85;
+
+/// Characters per token assumed until a response reports the real count.
+pub const INITIAL_CHARS_PER_TOKEN: f64 = 4.0;
+
+const QUESTION: &str =
+    "What is the secret passphrase mentioned above? Reply with only the passphrase.";
+
+/// Context windows to try: doubling from 2048, ending exactly at `max`.
+pub fn sizes(max: u32) -> Vec<u32> {
+    let mut sizes = Vec::new();
+    let mut size = FIRST_SIZE;
+    while size < max {
+        sizes.push(size);
+        size *= 2;
+    }
+    sizes.push(max.max(FIRST_SIZE));
+    sizes
+}
+
+fn passphrase(size: u32, depth: u8) -> String {
+    format!("amber-falcon-{size}-{depth}")
+}
+
+/// A prompt of about `tokens` tokens of numbered filler with the
+/// passphrase `depth` percent of the way in, ending with the question.
+pub fn haystack(tokens: u32, chars_per_token: f64, depth: u8, passphrase: &str) -> String {
+    let target = (f64::from(tokens) * chars_per_token) as usize;
+    let needle = format!("The secret passphrase is {passphrase}. Remember it.\n");
+    let mut prompt = String::with_capacity(target + needle.len() + QUESTION.len());
+    let mut placed = false;
+    let mut line = 1;
+    while prompt.len() < target {
+        if !placed && prompt.len() >= target * usize::from(depth) / 100 {
+            prompt.push_str(&needle);
+            placed = true;
+        }
+        prompt.push_str(&format!(
+            "Log entry {line}: the nightly build on runner {} finished without errors.\n",
+            line % 37
+        ));
+        line += 1;
+    }
+    if !placed {
+        prompt.push_str(&needle);
+    }
+    prompt.push('\n');
+    prompt.push_str(QUESTION);
+    prompt
+}
+
+/// How one context window did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub num_ctx: u32,
+    /// Depths at which the passphrase was recalled.
+    pub passed: usize,
+    /// Prompt tokens the server processed for the last depth. Far fewer
+    /// than the window means the server cut the prompt short.
+    pub prompt_tokens: Option<u64>,
+}
+
+impl Probe {
+    pub fn all_passed(&self) -> bool {
+        self.passed == DEPTHS.len()
+    }
+}
+
+/// The largest window that, along with every smaller one, recalled the
+/// passphrase at every depth.
+pub fn usable(probes: &[Probe]) -> Option<u32> {
+    probes
+        .iter()
+        .take_while(|probe| probe.all_passed())
+        .map(|probe| probe.num_ctx)
+        .last()
+}
+
+/// The first window where recall started failing.
+pub fn degradation_point(probes: &[Probe]) -> Option<u32> {
+    probes
+        .iter()
+        .find(|probe| !probe.all_passed())
+        .map(|probe| probe.num_ctx)
+}
+
+/// Runs the needle-in-a-haystack test at every depth with a `num_ctx`
+/// window. `chars_per_token` is refined from the token counts the server
+/// reports, so later (larger) prompts fill their window more accurately.
+pub async fn probe(
+    client: &Client,
+    host: &str,
+    model: &str,
+    num_ctx: u32,
+    chars_per_token: &mut f64,
+) -> Result<Probe, RuntimeError> {
+    let mut passed = 0;
+    let mut prompt_tokens = None;
+    for depth in DEPTHS {
+        let passphrase = passphrase(num_ctx, depth);
+        let prompt = haystack(
+            (f64::from(num_ctx) * FILL) as u32,
+            *chars_per_token,
+            depth,
+            &passphrase,
+        );
+        let prompt_len = prompt.len();
+        let request = GenerateRequest {
+            model: model.to_string(),
+            prompt,
+            options: Some(GenerationOptions {
+                temperature: Some(0.0),
+                num_predict: Some(32),
+                num_ctx: Some(num_ctx),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let completion = ollama::complete(client, host, &request).await?;
+        if completion.text.contains(&passphrase) {
+            passed += 1;
+        }
+        prompt_tokens = completion.metadata.prompt_tokens;
+        // A count at the window size may be a truncated prompt, which
+        // would skew the estimate.
+        if let Some(tokens) =
+            prompt_tokens.filter(|&tokens| tokens > 0 && tokens < u64::from(num_ctx))
+        {
+            *chars_per_token = prompt_len as f64 / tokens as f64;
+        }
+    }
+    Ok(Probe {
+        num_ctx,
+        passed,
+        prompt_tokens,
+    })
+}
+
+/// Probes `model` at growing context windows up to `max` (default: what
+/// the model reports), printing one result per line, until recall fails.
+/// With `save`, the usable window is written as the model's `num_ctx` in
+/// `config_path`.
+pub async fn run(
+    client: &Client,
+    host: &str,
+    model: &str,
+    max: Option<u32>,
+    config_path: &Path,
+    save: bool,
+) -> Result<(), RuntimeError> {
+    let reported = ollama::show_capabilities(client, host, model)
+        .await?
+        .context_length;
+    if let Some(reported) = reported {
+        eprintln!("[ai-coder] {model} reports a {reported}-token context window");
+    }
+    let max = max
+        .or_else(|| reported.and_then(|tokens| u32::try_from(tokens).ok()))
+        .unwrap_or(DEFAULT_MAX);
+
+    let mut chars_per_token = INITIAL_CHARS_PER_TOKEN;
+    let mut probes = Vec::new();
+    for num_ctx in sizes(max) {
+        eprintln!("[ai-coder] Probing {num_ctx} tokens...");
+        let probe = match probe(client, host, model, num_ctx, &mut chars_per_token).await {
+            Ok(probe) => probe,
+            // Usually out of memory; larger windows would fail too.
+            Err(err) => {
+                println!("{num_ctx}\tfailed");
+                eprintln!("[ai-coder] warning: probing {num_ctx} tokens failed: {err}");
+                if let Some(hint) = err.user_hint() {
+                    eprintln!("[ai-coder] hint: {hint}");
+                }
+                break;
+            }
+        };
+        let prompt = probe
+            .prompt_tokens
+            .map_or_else(String::new, |tokens| format!("\t{tokens} prompt tokens"));
+        println!(
+            "{num_ctx}\t{}/{} recalled{prompt}",
+            probe.passed,
+            DEPTHS.len()
+        );
+        let stop = probe.passed == 0;
+        probes.push(probe);
+        if stop {
+            break;
+        }
+    }
+
+    if let Some(point) = degradation_point(&probes) {
+        eprintln!("[ai-coder] Recall starts failing at {point} tokens");
+    }
+    let Some(usable) = usable(&probes) else {
+        return Err(RuntimeError::Usage(format!(
+            "{model} did not reliably recall the passphrase even at the smallest window"
+        )));
+    };
+    eprintln!("[ai-coder] Usable context for {model}: {usable} tokens");
+    if save {
+        config::save_num_ctx(config_path, model, usable)?;
+        eprintln!(
+            "[ai-coder] Saved num_ctx = {usable} under [models.\"{model}\"] in {}",
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{degradation_point, haystack, sizes, usable, Probe};
+
+    #[test]
+    fn sizes_double_up_to_the_maximum() {
+        assert_eq!(sizes(16384), vec![2048, 4096, 8192, 16384]);
+        assert_eq!(sizes(10000), vec![2048, 4096, 8192, 10000]);
+        assert_eq!(sizes(1024), vec![2048]);
+    }
+
+    #[test]
+    fn haystack_hides_the_needle_at_the_requested_depth() {
+        let prompt = haystack(1000, 4.0, 50, "amber-falcon-1");
+        let needle = prompt.find("amber-falcon-1").unwrap() as f64;
+
+        assert!((3_600..=4_400).contains(&prompt.len()));
+        assert!((0.45..0.55).contains(&(needle / prompt.len() as f64)));
+        assert!(prompt.ends_with("Reply with only the passphrase."));
+    }
+
+    #[test]
+    fn usable_window_stops_at_the_first_failure() {
+        let probe = |num_ctx, passed| Probe {
+            num_ctx,
+            passed,
+            prompt_tokens: None,
+        };
+        let probes = [
+            probe(2048, 3),
+            probe(4096, 3),
+            probe(8192, 2),
+            probe(16384, 3),
+        ];
+
+        assert_eq!(usable(&probes), Some(4096));
+        assert_eq!(degradation_point(&probes), Some(8192));
+        assert_eq!(usable(&[probe(2048, 1)]), None);
+        assert_eq!(degradation_point(&probes[..2]), None);
+    }
+}
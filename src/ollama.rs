@@ -0,0 +1,971 @@
+use crate::error::RuntimeError;
+use base64::Engine;
+use futures_util::{stream, Stream, StreamExt};
+use reqwest::{Body, Client, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Decoded frames buffered ahead of the consumer before the provider read
+/// is paused.
+const STREAM_BUFFER_FRAMES: usize = 256;
+
+/// How long the buffer may stay full before the consumer is reported as slow.
+const SLOW_CONSUMER_WARNING: Duration = Duration::from_secs(5);
+
+/// How long to wait for the end of the body after the final frame. Reading
+/// it lets the connection go back to the pool instead of being closed.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct GenerateRequest {
+    pub model: String,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub stream: bool,
+    /// Send `prompt` verbatim, bypassing the model's chat template.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub raw: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<GenerationOptions>,
+    /// Base64-encoded images for vision models such as llava.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// Structured output: `"json"` or a JSON schema the answer must match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+}
+
+/// Sampling parameters passed through as Ollama's `options` object.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct GenerationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Fixed sampling seed, so the same prompt gives the same output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Context window in tokens; the server's default when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaResponse {
+    #[serde(default)]
+    response: String,
+    /// Reasoning from models Ollama runs with a separate thinking channel.
+    #[serde(default)]
+    thinking: String,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+    /// Nanoseconds, as reported by Ollama.
+    total_duration: Option<u64>,
+    /// Nanoseconds spent generating the completion, excluding load and prompt time.
+    eval_duration: Option<u64>,
+}
+
+/// Generation statistics reported with the final response frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseMetadata {
+    pub model: String,
+    pub done_reason: Option<String>,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub total_duration: Option<Duration>,
+    pub eval_duration: Option<Duration>,
+    /// What a reasoning model thought before answering, whether Ollama
+    /// reported it separately or it was stripped from `<think>` tags.
+    pub reasoning: Option<String>,
+}
+
+impl ResponseMetadata {
+    /// Generation speed, excluding model load and prompt processing.
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let seconds = self.eval_duration?.as_secs_f64();
+        let tokens = self.completion_tokens? as f64;
+        (seconds > 0.0).then(|| tokens / seconds)
+    }
+
+    fn from_final(model: &str, response: &OllamaResponse) -> Self {
+        ResponseMetadata {
+            model: model.to_string(),
+            done_reason: response.done_reason.clone(),
+            prompt_tokens: response.prompt_eval_count,
+            completion_tokens: response.eval_count,
+            total_duration: response.total_duration.map(Duration::from_nanos),
+            eval_duration: response.eval_duration.map(Duration::from_nanos),
+            reasoning: (!response.thinking.is_empty()).then(|| response.thinking.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub text: String,
+    pub metadata: ResponseMetadata,
+    /// The stream broke and the retried answer didn't continue the partial
+    /// one, so it was written out again in full after it. A file the
+    /// answer was streamed into should be rewritten with `text`.
+    pub restarted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<TagsModel>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagsModel {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ShowResponse {
+    /// Missing before Ollama 0.6.
+    capabilities: Option<Vec<String>>,
+    #[serde(default)]
+    model_info: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A feature a model may or may not have, named as in `/api/show`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Completion,
+    Vision,
+    Tools,
+    /// Fill-in-the-middle through Ollama's `suffix` parameter.
+    Insert,
+    Thinking,
+    Embedding,
+}
+
+impl Capability {
+    fn as_str(self) -> &'static str {
+        match self {
+            Capability::Completion => "completion",
+            Capability::Vision => "vision",
+            Capability::Tools => "tools",
+            Capability::Insert => "insert",
+            Capability::Thinking => "thinking",
+            Capability::Embedding => "embedding",
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a model supports, as reported by `/api/show`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `None` when the server doesn't report capabilities.
+    names: Option<Vec<String>>,
+    pub context_length: Option<u64>,
+}
+
+impl Capabilities {
+    /// Whether the model supports `capability`. Unknown capabilities count
+    /// as supported so older servers aren't locked out.
+    pub fn supports(&self, capability: Capability) -> bool {
+        match &self.names {
+            Some(names) => names.iter().any(|name| name == capability.as_str()),
+            None => true,
+        }
+    }
+
+    fn from_show(show: ShowResponse) -> Self {
+        let context_length = show
+            .model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64());
+        Capabilities {
+            names: show.capabilities,
+            context_length,
+        }
+    }
+}
+
+/// One progress frame from `/api/pull`.
+#[derive(Deserialize, Debug, Default)]
+pub struct PullProgress {
+    #[serde(default)]
+    pub status: String,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+    error: Option<String>,
+}
+
+/// A `/api/create` request: a new model built from a base model or
+/// uploaded weights, with optional adapters and prompt settings.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct CreateRequest {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Uploaded weights, file name to blob digest.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+    /// Uploaded LoRA adapters, file name to blob digest.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub adapters: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub parameters: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Splits a byte stream into newline-delimited JSON frames, buffering
+/// partial lines across chunk boundaries.
+#[derive(Default)]
+struct NdjsonDecoder {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut frames = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = line.trim_ascii();
+            if !line.is_empty() {
+                frames.push(line.to_vec());
+            }
+        }
+        frames
+    }
+
+    /// Returns a trailing frame that was not newline-terminated.
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = rest.trim_ascii();
+        (!rest.is_empty()).then(|| rest.to_vec())
+    }
+}
+
+enum StreamEvent {
+    Chunk(OllamaResponse),
+    Malformed(serde_json::Error),
+    Interrupted(String),
+}
+
+/// Provider response body decoded on a separate task into a bounded
+/// channel. When the consumer falls behind (slow pipe, pager), the buffer
+/// fills and the reader stops polling the connection, so memory stays
+/// bounded and backpressure reaches the provider through TCP.
+struct ResponseStream {
+    events: mpsc::Receiver<StreamEvent>,
+    reader: JoinHandle<()>,
+}
+
+impl ResponseStream {
+    fn spawn<S, B, E>(body: S, capacity: usize) -> Self
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send,
+        E: Display + Send,
+    {
+        let (tx, events) = mpsc::channel(capacity);
+        let reader = tokio::spawn(async move {
+            let mut body = std::pin::pin!(body);
+            let mut decoder = NdjsonDecoder::default();
+            let mut warned = false;
+
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        let _ = tx.send(StreamEvent::Interrupted(err.to_string())).await;
+                        return;
+                    }
+                };
+
+                for frame in decoder.push(chunk.as_ref()) {
+                    if !Self::send(&tx, Self::parse(&frame), &mut warned).await {
+                        return;
+                    }
+                }
+            }
+
+            if let Some(frame) = decoder.finish() {
+                Self::send(&tx, Self::parse(&frame), &mut warned).await;
+            }
+        });
+
+        ResponseStream { events, reader }
+    }
+
+    fn parse(frame: &[u8]) -> StreamEvent {
+        match serde_json::from_slice(frame) {
+            Ok(parsed) => StreamEvent::Chunk(parsed),
+            Err(err) => StreamEvent::Malformed(err),
+        }
+    }
+
+    /// Waits for buffer space, warning once if the consumer stalls. Returns
+    /// false when the consumer has gone away.
+    async fn send(tx: &mpsc::Sender<StreamEvent>, event: StreamEvent, warned: &mut bool) -> bool {
+        let permit = match time::timeout(SLOW_CONSUMER_WARNING, tx.reserve()).await {
+            Ok(permit) => permit,
+            Err(_) => {
+                if !*warned {
+                    eprintln!(
+                        "\n[ai-coder] warning: output is not keeping up; pausing the provider stream"
+                    );
+                    *warned = true;
+                }
+                tx.reserve().await
+            }
+        };
+
+        match permit {
+            Ok(permit) => {
+                permit.send(event);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    async fn next(&mut self) -> Option<StreamEvent> {
+        self.events.recv().await
+    }
+
+    /// Reads the rest of the body, giving up after [`DRAIN_TIMEOUT`].
+    async fn drain(mut self) {
+        let _ = time::timeout(DRAIN_TIMEOUT, async {
+            while self.next().await.is_some() {}
+            let _ = (&mut self.reader).await;
+        })
+        .await;
+    }
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// What to print after a buffered retry, given what was already streamed.
+/// `None` means the retry diverged and cannot be spliced onto the partial output.
+fn splice<'a>(streamed: &str, buffered: &'a str) -> Option<&'a str> {
+    buffered.strip_prefix(streamed)
+}
+
+/// Maps an Ollama error message onto a specific `RuntimeError`. Ollama only
+/// reports these conditions as free-form strings, so this matches on the
+/// phrases used by Ollama and the llama.cpp runner.
+/// Whether `message` is Ollama's missing-model error, `model "name" not
+/// found, try pulling it first` or `model 'name' not found`, rather than
+/// some other 404 such as a missing blob.
+fn is_model_not_found(message: &str) -> bool {
+    let Some(rest) = message.strip_prefix("model ") else {
+        return false;
+    };
+    ['"', '\''].iter().any(|&quote| {
+        rest.strip_prefix(quote)
+            .and_then(|rest| rest.split_once(quote))
+            .is_some_and(|(name, rest)| !name.is_empty() && rest.starts_with(" not found"))
+    })
+}
+
+fn classify_error(status: Option<u16>, message: String, model: &str) -> RuntimeError {
+    let lower = message.to_lowercase();
+    let model = model.to_string();
+
+    if is_model_not_found(&lower) {
+        RuntimeError::ModelNotFound { model }
+    } else if lower.contains("out of memory")
+        || lower.contains("more system memory")
+        || lower.contains("insufficient memory")
+        || lower.contains("cudamalloc failed")
+    {
+        RuntimeError::ResourceExhausted { model, message }
+    } else if lower.contains("context length")
+        || lower.contains("context size")
+        || lower.contains("context window")
+        || lower.contains("prompt is too long")
+    {
+        RuntimeError::ContextOverflow { model, message }
+    } else {
+        RuntimeError::Provider { status, message }
+    }
+}
+
+/// Turns a non-success response into a `RuntimeError`, using Ollama's
+/// `{"error": "..."}` body as the message when present.
+async fn check_status(response: Response, model: &str) -> Result<Response, RuntimeError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await?;
+    let message = serde_json::from_str::<ErrorBody>(&body)
+        .map(|body| body.error)
+        .unwrap_or(body);
+
+    Err(classify_error(Some(status.as_u16()), message, model))
+}
+
+async fn generate_buffered(
+    client: &Client,
+    api_url: &str,
+    request: &GenerateRequest,
+) -> Result<Completion, RuntimeError> {
+    let request = GenerateRequest {
+        stream: false,
+        ..request.clone()
+    };
+    let response = client.post(api_url).json(&request).send().await?;
+    let response = check_status(response, &request.model).await?;
+    let parsed: OllamaResponse = serde_json::from_slice(&response.bytes().await?)?;
+    match parsed.error {
+        Some(message) => Err(classify_error(None, message, &request.model)),
+        None => Ok(Completion {
+            metadata: ResponseMetadata::from_final(&request.model, &parsed),
+            text: parsed.response,
+            restarted: false,
+        }),
+    }
+}
+
+/// Reads an image for [`GenerateRequest::images`]. Only PNG and JPEG are
+/// accepted, the formats Ollama's vision models decode.
+pub fn load_image(path: &Path) -> Result<String, RuntimeError> {
+    let bytes = std::fs::read(path)?;
+    if !bytes.starts_with(b"\x89PNG") && !bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Err(RuntimeError::Usage(format!(
+            "{} is not a PNG or JPEG image",
+            path.display()
+        )));
+    }
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Polls `host` with backoff until it answers, e.g. after Ollama was
+/// restarted to free VRAM. Gives up after `limit`.
+pub async fn wait_until_ready(client: &Client, host: &str, limit: Duration) -> bool {
+    let deadline = time::Instant::now() + limit;
+    let mut delay = Duration::from_millis(500);
+    loop {
+        let response = client
+            .get(format!("{}/api/version", host))
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await;
+        if response.is_ok_and(|response| response.status().is_success()) {
+            return true;
+        }
+        let now = time::Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        time::sleep(delay.min(deadline - now)).await;
+        delay = (delay * 2).min(Duration::from_secs(8));
+    }
+}
+
+/// Names of the models installed on `host`, as listed by `/api/tags`.
+pub async fn list_models(client: &Client, host: &str) -> Result<Vec<String>, RuntimeError> {
+    let response = client.get(format!("{}/api/tags", host)).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(RuntimeError::Provider {
+            status: Some(status.as_u16()),
+            message: response.text().await?,
+        });
+    }
+    let tags: TagsResponse = serde_json::from_slice(&response.bytes().await?)?;
+    Ok(tags.models.into_iter().map(|model| model.name).collect())
+}
+
+/// What `model` on `host` supports, from `/api/show`.
+pub async fn show_capabilities(
+    client: &Client,
+    host: &str,
+    model: &str,
+) -> Result<Capabilities, RuntimeError> {
+    let response = client
+        .post(format!("{}/api/show", host))
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await?;
+    let response = check_status(response, model).await?;
+    let show: ShowResponse = serde_json::from_slice(&response.bytes().await?)?;
+    Ok(Capabilities::from_show(show))
+}
+
+/// Downloads `model` onto `host`, reporting each progress frame.
+pub async fn pull_model<F>(
+    client: &Client,
+    host: &str,
+    model: &str,
+    mut on_progress: F,
+) -> Result<(), RuntimeError>
+where
+    F: FnMut(&PullProgress),
+{
+    let response = client
+        .post(format!("{}/api/pull", host))
+        .json(&serde_json::json!({ "model": model, "stream": true }))
+        .send()
+        .await?;
+    let response = check_status(response, model).await?;
+
+    let mut body = response.bytes_stream();
+    let mut decoder = NdjsonDecoder::default();
+    let mut frames = Vec::new();
+    loop {
+        let chunk = body.next().await.transpose()?;
+        match &chunk {
+            Some(chunk) => frames.extend(decoder.push(chunk)),
+            None => frames.extend(decoder.finish()),
+        }
+        for frame in frames.drain(..) {
+            let mut progress: PullProgress = serde_json::from_slice(&frame)?;
+            if let Some(message) = progress.error.take() {
+                return Err(classify_error(None, message, model));
+            }
+            on_progress(&progress);
+        }
+        if chunk.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// Bytes read at a time when hashing and uploading blobs.
+const BLOB_CHUNK: usize = 1 << 20;
+
+/// The `sha256:...` digest of the file at `path`, read a chunk at a time.
+async fn file_digest(path: &Path) -> io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; BLOB_CHUNK];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(format!("sha256:{:x}", hasher.finalize()));
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+/// Uploads the file at `path` to `host` unless a blob with the same digest
+/// is already there, streaming it rather than loading it into memory.
+/// Returns the digest (`sha256:...`) to refer to it by.
+pub async fn push_blob(client: &Client, host: &str, path: &Path) -> Result<String, RuntimeError> {
+    let digest = file_digest(path).await?;
+    let url = format!("{}/api/blobs/{digest}", host);
+    if client.head(&url).send().await?.status().is_success() {
+        return Ok(digest);
+    }
+    let file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let chunks = stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; BLOB_CHUNK];
+        let read = file.read(&mut chunk).await?;
+        chunk.truncate(read);
+        Ok::<_, io::Error>((read > 0).then_some((chunk, file)))
+    });
+    let response = client
+        .post(&url)
+        .header(reqwest::header::CONTENT_LENGTH, len)
+        .body(Body::wrap_stream(chunks))
+        .send()
+        .await?;
+    check_status(response, &digest).await?;
+    Ok(digest)
+}
+
+/// Creates a model on `host` from `request`, waiting until it is ready.
+pub async fn create_model(
+    client: &Client,
+    host: &str,
+    request: &CreateRequest,
+) -> Result<(), RuntimeError> {
+    let mut body = serde_json::to_value(request)?;
+    body["stream"] = false.into();
+    let response = client
+        .post(format!("{}/api/create", host))
+        .json(&body)
+        .send()
+        .await?;
+    check_status(response, &request.model).await?;
+    Ok(())
+}
+
+/// Runs a completion without streaming, for callers that need the whole
+/// response at once (editor integrations).
+pub async fn complete(
+    client: &Client,
+    host: &str,
+    request: &GenerateRequest,
+) -> Result<Completion, RuntimeError> {
+    generate_buffered(client, &format!("{}/api/generate", host), request).await
+}
+
+/// Streams a completion into `out`. If the stream breaks after it has
+/// started, the request is retried once in buffered mode and the result
+/// spliced onto what was already written.
+///
+/// Reasoning the provider reports separately is collected into the
+/// metadata, and also written to `out` inside `<think>` tags when
+/// `show_thinking` is set.
+pub async fn generate<W: Write>(
+    client: &Client,
+    host: &str,
+    request: &GenerateRequest,
+    out: &mut W,
+    show_thinking: bool,
+) -> Result<Completion, RuntimeError> {
+    let api_url = format!("{}/api/generate", host);
+
+    let response = client.post(&api_url).json(request).send().await?;
+    let response = check_status(response, &request.model).await?;
+
+    let mut stream = ResponseStream::spawn(response.bytes_stream(), STREAM_BUFFER_FRAMES);
+    let mut streamed = String::new();
+    let mut reasoning = String::new();
+    let mut thinking_open = false;
+    let mut metadata = None;
+    let mut interruption = None;
+
+    while let Some(event) = stream.next().await {
+        match event {
+            StreamEvent::Chunk(parsed) => {
+                if let Some(message) = parsed.error {
+                    return Err(classify_error(None, message, &request.model));
+                }
+                if show_thinking && !parsed.thinking.is_empty() && !thinking_open {
+                    writeln!(out, "<think>")?;
+                    thinking_open = true;
+                }
+                if show_thinking {
+                    write!(out, "{}", parsed.thinking)?;
+                }
+                reasoning.push_str(&parsed.thinking);
+                if thinking_open && (!parsed.response.is_empty() || parsed.done) {
+                    write!(out, "\n</think>\n\n")?;
+                    thinking_open = false;
+                }
+
+                write!(out, "{}", parsed.response)?;
+                out.flush()?; // Ensure immediate rendering
+                streamed.push_str(&parsed.response);
+
+                if parsed.done {
+                    let mut done = ResponseMetadata::from_final(&request.model, &parsed);
+                    done.reasoning = (!reasoning.is_empty()).then(|| reasoning.clone());
+                    metadata = Some(done);
+                    break;
+                }
+            }
+            StreamEvent::Malformed(err) => return Err(err.into()),
+            StreamEvent::Interrupted(reason) => {
+                interruption = Some(reason);
+                break;
+            }
+        }
+    }
+    if metadata.is_some() {
+        stream.drain().await;
+    } else {
+        drop(stream);
+    }
+
+    if let Some(metadata) = metadata {
+        return Ok(Completion {
+            text: streamed,
+            metadata,
+            restarted: false,
+        });
+    }
+    let reason = interruption.unwrap_or_else(|| "stream ended before completion".to_string());
+
+    eprintln!("\n[ai-coder] warning: stream interrupted ({reason}); retrying without streaming");
+    let mut buffered = generate_buffered(client, &api_url, request).await?;
+
+    match splice(&streamed, &buffered.text) {
+        Some(rest) => write!(out, "{}", rest)?,
+        None => {
+            eprintln!("[ai-coder] warning: retried response differs from the partial output; printing it in full");
+            write!(out, "\n{}", buffered.text)?;
+            buffered.restarted = true;
+        }
+    }
+    out.flush()?;
+
+    Ok(buffered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        classify_error, load_image, splice, wait_until_ready, Capabilities, Capability,
+        GenerateRequest, NdjsonDecoder, OllamaResponse, ResponseStream, StreamEvent,
+    };
+    use crate::error::RuntimeError;
+    use futures_util::{stream, StreamExt};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn decoder_reassembles_frames_split_across_chunks() {
+        let mut decoder = NdjsonDecoder::default();
+
+        assert!(decoder.push(br#"{"response":"fn"#).is_empty());
+        let frames = decoder.push(b"\"}\n{\"response\":\" main\"}\n{\"done\":");
+
+        assert_eq!(
+            frames,
+            vec![
+                br#"{"response":"fn"}"#.to_vec(),
+                br#"{"response":" main"}"#.to_vec()
+            ]
+        );
+        assert_eq!(decoder.finish(), Some(br#"{"done":"#.to_vec()));
+    }
+
+    #[test]
+    fn splice_appends_only_the_missing_suffix() {
+        assert_eq!(splice("fn ma", "fn main() {}"), Some("in() {}"));
+        assert_eq!(splice("", "fn main() {}"), Some("fn main() {}"));
+        assert_eq!(splice("def ", "fn main() {}"), None);
+    }
+
+    #[test]
+    fn classifies_ollama_error_messages() {
+        let classify = |status, message: &str| {
+            classify_error(status, message.to_string(), "qwen2.5-coder:14b")
+        };
+
+        assert!(matches!(
+            classify(
+                Some(404),
+                "model \"qwen2.5-coder:14b\" not found, try pulling it first"
+            ),
+            RuntimeError::ModelNotFound { .. }
+        ));
+        assert!(matches!(
+            classify(
+                Some(500),
+                "model requires more system memory (12.1 GiB) than is available (7.5 GiB)"
+            ),
+            RuntimeError::ResourceExhausted { .. }
+        ));
+        assert!(matches!(
+            classify(
+                None,
+                "llama runner process has terminated: cudaMalloc failed: out of memory"
+            ),
+            RuntimeError::ResourceExhausted { .. }
+        ));
+        assert!(matches!(
+            classify(Some(400), "the request exceeds the available context size"),
+            RuntimeError::ContextOverflow { .. }
+        ));
+        assert!(matches!(
+            classify(Some(404), "model 'qwen2.5-coder:14b' not found"),
+            RuntimeError::ModelNotFound { .. }
+        ));
+        // Other 404s, such as a missing blob or an unknown route, are not
+        // a missing model.
+        assert!(matches!(
+            classify(Some(404), "blob sha256:abc not found"),
+            RuntimeError::Provider { .. }
+        ));
+        assert!(matches!(
+            classify(Some(404), "404 page not found"),
+            RuntimeError::Provider { .. }
+        ));
+        assert!(matches!(
+            classify(Some(400), "invalid options"),
+            RuntimeError::Provider {
+                status: Some(400),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn images_are_sent_base64_encoded_only_when_attached() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = dir.path().join("shot.png");
+        std::fs::write(&png, b"\x89PNG\r\n").unwrap();
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "hello").unwrap();
+
+        assert_eq!(load_image(&png).unwrap(), "iVBORw0K");
+        assert!(matches!(load_image(&text), Err(RuntimeError::Usage(_))));
+
+        let plain = serde_json::to_value(GenerateRequest::default()).unwrap();
+        assert!(plain.get("images").is_none());
+    }
+
+    #[test]
+    fn capabilities_come_from_show_and_default_to_supported() {
+        let show = |json| Capabilities::from_show(serde_json::from_str(json).unwrap());
+
+        let coder = show(
+            r#"{"capabilities": ["completion", "insert"],
+                "model_info": {"qwen2.context_length": 32768, "qwen2.block_count": 28}}"#,
+        );
+        assert!(coder.supports(Capability::Insert));
+        assert!(!coder.supports(Capability::Vision));
+        assert_eq!(coder.context_length, Some(32768));
+
+        let old_server = show(r#"{"modelfile": "FROM llava"}"#);
+        assert!(old_server.supports(Capability::Vision));
+        assert_eq!(old_server.context_length, None);
+    }
+
+    #[tokio::test]
+    async fn response_stream_stops_reading_when_buffer_is_full() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let counter = polled.clone();
+        let body = stream::iter(0..10)
+            .map(|i| Ok::<_, String>(format!("{{\"response\":\"{i}\"}}\n").into_bytes()))
+            .inspect(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+
+        let mut response = ResponseStream::spawn(body, 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Two buffered frames plus the one waiting for space.
+        assert_eq!(polled.load(Ordering::SeqCst), 3);
+
+        let mut received = String::new();
+        while let Some(event) = response.next().await {
+            match event {
+                StreamEvent::Chunk(parsed) => received.push_str(&parsed.response),
+                _ => panic!("unexpected event"),
+            }
+        }
+        assert_eq!(received, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn response_stream_reports_transport_errors() {
+        let body = stream::iter(vec![
+            Ok(b"{\"response\":\"fn\"}\n".to_vec()),
+            Err("connection reset".to_string()),
+        ]);
+
+        let mut response = ResponseStream::spawn(body, 4);
+
+        assert!(matches!(response.next().await, Some(StreamEvent::Chunk(_))));
+        assert!(
+            matches!(response.next().await, Some(StreamEvent::Interrupted(reason)) if reason == "connection reset")
+        );
+        assert!(response.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn draining_reads_the_body_to_the_end() {
+        let ended = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&ended);
+        let body = stream::iter(vec![Ok::<_, String>(
+            b"{\"response\":\"\",\"done\":true}\n".to_vec(),
+        )])
+        .chain(stream::once(async move {
+            flag.store(true, Ordering::SeqCst);
+            Ok(Vec::new())
+        }));
+
+        let mut response = ResponseStream::spawn(body, 1);
+        assert!(matches!(response.next().await, Some(StreamEvent::Chunk(frame)) if frame.done));
+        response.drain().await;
+
+        assert!(ended.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_server_to_come_back() {
+        let client = reqwest::Client::new();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert!(!wait_until_ready(&client, &host, Duration::from_millis(100)).await);
+
+        let listener = std::net::TcpListener::bind(host.trim_start_matches("http://")).unwrap();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut socket, _) = listener.accept().unwrap();
+            let _ = socket.read(&mut [0; 1024]);
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n");
+        });
+        assert!(wait_until_ready(&client, &host, Duration::from_secs(5)).await);
+    }
+
+    proptest! {
+        #[test]
+        fn decoder_recovers_frames_however_the_body_is_split(
+            texts in vec(any::<String>(), 0..8),
+            crlf in any::<bool>(),
+            cuts in vec(any::<usize>(), 0..6),
+        ) {
+            let ending = if crlf { "\r\n" } else { "\n" };
+            let body: String = texts
+                .iter()
+                .map(|text| serde_json::json!({ "response": text }).to_string() + ending)
+                .collect();
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (body.len() + 1)).collect();
+            cuts.sort();
+
+            let mut decoder = NdjsonDecoder::default();
+            let mut frames = Vec::new();
+            let mut start = 0;
+            for end in cuts.into_iter().chain([body.len()]) {
+                frames.extend(decoder.push(&body.as_bytes()[start..end]));
+                start = end;
+            }
+            frames.extend(decoder.finish());
+
+            let decoded: Vec<String> = frames
+                .iter()
+                .map(|frame| serde_json::from_slice::<OllamaResponse>(frame).unwrap().response)
+                .collect();
+            prop_assert_eq!(decoded, texts);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::ollama::ResponseMetadata;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Writes everything to both `primary` and `secondary`, e.g. the terminal
+/// and a `--save` file.
+pub struct Tee<A, B> {
+    primary: A,
+    secondary: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        Tee { primary, secondary }
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.primary.write_all(buf)?;
+        self.secondary.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.secondary.flush()
+    }
+}
+
+/// Replaces everything written to a `--save` file so far with `text`, for
+/// answers that were printed again in full after a broken stream (see
+/// [`crate::ollama::Completion::restarted`]).
+pub fn rewrite(file: &mut File, text: &str) -> io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(text.as_bytes())
+}
+
+/// Metadata block appended to saved responses. It is an HTML comment so
+/// Markdown renderers hide it while it stays easy to grep.
+pub fn metadata_footer(metadata: &ResponseMetadata, host: &str) -> String {
+    let mut footer = String::from("\n\n<!-- ai-coder\n");
+    footer.push_str(&format!("model: {}\n", metadata.model));
+    footer.push_str(&format!("host: {}\n", host));
+    if let Some(reason) = &metadata.done_reason {
+        footer.push_str(&format!("done_reason: {}\n", reason));
+    }
+    if let Some(tokens) = metadata.prompt_tokens {
+        footer.push_str(&format!("prompt_tokens: {}\n", tokens));
+    }
+    if let Some(tokens) = metadata.completion_tokens {
+        footer.push_str(&format!("completion_tokens: {}\n", tokens));
+    }
+    if let Some(duration) = metadata.total_duration {
+        footer.push_str(&format!("duration_ms: {}\n", duration.as_millis()));
+    }
+    footer.push_str("-->\n");
+    footer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metadata_footer, rewrite, Tee};
+    use crate::ollama::ResponseMetadata;
+    use std::fs;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn tee_writes_to_both_sinks() {
+        let mut terminal = Vec::new();
+        let mut file = Vec::new();
+
+        write!(Tee::new(&mut terminal, &mut file), "fn main() {{}}").unwrap();
+
+        assert_eq!(terminal, b"fn main() {}");
+        assert_eq!(file, b"fn main() {}");
+    }
+
+    #[test]
+    fn restarted_answers_replace_the_partial_one_in_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("answer.md");
+        let mut file = fs::File::create(&path).unwrap();
+        let mut terminal = Vec::new();
+
+        // What a broken stream followed by a diverging retry writes.
+        write!(Tee::new(&mut terminal, &mut file), "fn ma\nfn main() {{}}").unwrap();
+        rewrite(&mut file, "fn main() {}").unwrap();
+        write!(file, "\n<!-- footer -->").unwrap();
+
+        assert_eq!(terminal, b"fn ma\nfn main() {}");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "fn main() {}\n<!-- footer -->"
+        );
+    }
+
+    #[test]
+    fn footer_lists_only_reported_fields() {
+        let metadata = ResponseMetadata {
+            model: "qwen2.5-coder".to_string(),
+            completion_tokens: Some(42),
+            total_duration: Some(Duration::from_millis(1500)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            metadata_footer(&metadata, "http://localhost:11434"),
+            "\n\n<!-- ai-coder\nmodel: qwen2.5-coder\nhost: http://localhost:11434\ncompletion_tokens: 42\nduration_ms: 1500\n-->\n"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::error::RuntimeError;
+use crate::git;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+/// A named prompt stored under `[prompts.<name>]` in the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    pub template: String,
+    pub description: Option<String>,
+}
+
+/// Names of the `{{variable}}` placeholders in `template`, in order of first use.
+pub fn variables(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = after[..end].trim();
+        if !name.is_empty() && !names.iter().any(|existing| existing == name) {
+            names.push(name.to_string());
+        }
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+/// Substitutes every placeholder, failing if any variable has no value.
+pub fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String, RuntimeError> {
+    let missing: Vec<String> = variables(template)
+        .into_iter()
+        .filter(|name| !vars.contains_key(name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(RuntimeError::Usage(format!(
+            "missing value for {}; pass --var NAME=VALUE",
+            missing
+                .iter()
+                .map(|name| format!("{{{{{name}}}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        match vars.get(after[..end].trim()) {
+            Some(replacement) => rendered.push_str(replacement),
+            None => rendered.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Values for the built-in variables: `{{file}}` (contents of `--file`)
+/// and `{{diff}}` (uncommitted changes from `git diff HEAD`).
+pub fn builtin_variable(name: &str, file: Option<&Path>) -> Option<Result<String, RuntimeError>> {
+    match name {
+        "file" => Some(match file {
+            Some(path) => fs::read_to_string(path).map_err(RuntimeError::from),
+            None => Err(RuntimeError::Usage(
+                "this prompt uses {{file}}; pass --file PATH".to_string(),
+            )),
+        }),
+        "diff" => Some(git::diff(&["HEAD"])),
+        _ => None,
+    }
+}
+
+/// Adds or replaces `[prompts.<name>]` in the config file, keeping the rest
+/// of the file (including comments) intact.
+pub fn save(
+    config_path: &Path,
+    name: &str,
+    template: &str,
+    description: Option<&str>,
+) -> Result<(), RuntimeError> {
+    let config_error = |source: Box<dyn std::error::Error + Send + Sync>| RuntimeError::Config {
+        path: config_path.to_path_buf(),
+        source,
+    };
+
+    let content = if config_path.exists() {
+        fs::read_to_string(config_path)?
+    } else {
+        String::new()
+    };
+    let mut doc: DocumentMut = content.parse().map_err(|err| config_error(Box::new(err)))?;
+
+    let prompts = doc
+        .entry("prompts")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .ok_or_else(|| config_error("`prompts` must be a table".into()))?;
+
+    let mut entry = Table::new();
+    entry["template"] = value(template);
+    if let Some(description) = description {
+        entry["description"] = value(description);
+    }
+    prompts.insert(name, Item::Table(entry));
+
+    fs::write(config_path, doc.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, save, variables};
+    use crate::config::load_file_config;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[test]
+    fn variables_are_unique_and_ordered() {
+        assert_eq!(
+            variables("Review {{ diff }} against {{file}} and {{diff}} again"),
+            vec!["diff".to_string(), "file".to_string()]
+        );
+    }
+
+    #[test]
+    fn render_substitutes_and_reports_missing_values() {
+        let vars = BTreeMap::from([("lang".to_string(), "Rust".to_string())]);
+
+        assert_eq!(
+            render("Write {{lang}} tests", &vars).unwrap(),
+            "Write Rust tests"
+        );
+        let err = render("Port {{file}} to {{lang}}", &vars).unwrap_err();
+        assert!(err.to_string().contains("{{file}}"));
+    }
+
+    #[test]
+    fn save_preserves_existing_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".ai-coder.toml");
+        fs::write(&path, "# team defaults\nmodel = \"deepseek-coder-v2\"\n").unwrap();
+
+        save(
+            &path,
+            "changelog",
+            "Summarize {{diff}}",
+            Some("Draft a changelog"),
+        )
+        .unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# team defaults\n"));
+        let config = load_file_config(&path).unwrap();
+        assert_eq!(config.model.as_deref(), Some("deepseek-coder-v2"));
+        assert_eq!(config.prompts["changelog"].template, "Summarize {{diff}}");
+    }
+}
@@ -0,0 +1,101 @@
+use crate::error::RuntimeError;
+use crate::git;
+
+/// Commit bodies are cut to this many characters to keep long ranges
+/// within the context window.
+const MAX_BODY_CHARS: usize = 300;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hash: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Non-merge commits in `range` (e.g. `v1.2.0..v1.3.0`), oldest first.
+pub fn commits(range: &str) -> Result<Vec<Commit>, RuntimeError> {
+    let log = git::run(&[
+        "log",
+        "--no-merges",
+        "--reverse",
+        "--format=%h%x1f%s%x1f%b%x1e",
+        range,
+        "--",
+    ])?;
+    Ok(parse_log(&log))
+}
+
+/// Parses records of `hash \x1f subject \x1f body`, each ended by `\x1e`.
+fn parse_log(log: &str) -> Vec<Commit> {
+    log.split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(3, '\x1f');
+            let hash = fields.next().filter(|hash| !hash.is_empty())?;
+            Some(Commit {
+                hash: hash.to_string(),
+                subject: fields.next().unwrap_or_default().to_string(),
+                body: fields.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn build_prompt(range: &str, commits: &[Commit]) -> String {
+    let mut prompt = format!(
+        "Draft release notes in Markdown for the changes in {range}, based on the commits below.\n\
+         Group the changes by area (features, fixes, performance, documentation, internal), \
+         lead with what matters most to users, and call out breaking changes in their own section. \
+         Keep PR and issue references such as (#123). Leave out sections with no changes.\n\n\
+         Commits:\n"
+    );
+    for commit in commits {
+        prompt.push_str(&format!("- {} {}\n", commit.hash, commit.subject));
+        if !commit.body.is_empty() {
+            let body: String = commit.body.chars().take(MAX_BODY_CHARS).collect();
+            prompt.push_str(&format!("  {}\n", body.replace('\n', "\n  ")));
+        }
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_prompt, parse_log, Commit};
+
+    #[test]
+    fn parses_git_log_records() {
+        let log = "a1b2c3d\x1fAdd --image (#42)\x1fVision models.\n\x1e\n\
+                   e4f5a6b\x1fFix typo\x1f\x1e\n";
+
+        assert_eq!(
+            parse_log(log),
+            vec![
+                Commit {
+                    hash: "a1b2c3d".to_string(),
+                    subject: "Add --image (#42)".to_string(),
+                    body: "Vision models.".to_string(),
+                },
+                Commit {
+                    hash: "e4f5a6b".to_string(),
+                    subject: "Fix typo".to_string(),
+                    body: String::new(),
+                },
+            ]
+        );
+        assert!(parse_log("").is_empty());
+    }
+
+    #[test]
+    fn prompt_lists_each_commit_with_its_body_indented() {
+        let commits = [Commit {
+            hash: "a1b2c3d".to_string(),
+            subject: "Add profiles".to_string(),
+            body: "Work and home.\nWith inheritance.".to_string(),
+        }];
+
+        let prompt = build_prompt("v1.0.0..v1.1.0", &commits);
+
+        assert!(prompt.contains("changes in v1.0.0..v1.1.0"));
+        assert!(prompt.ends_with("- a1b2c3d Add profiles\n  Work and home.\n  With inheritance.\n"));
+    }
+}
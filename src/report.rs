@@ -0,0 +1,306 @@
+use crate::error::RuntimeError;
+use crate::git;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TODO_MARKERS: [&str; 4] = ["TODO", "FIXME", "HACK", "XXX"];
+
+/// Items beyond this are left out of the prompt (but not the `--raw` output)
+/// so the request fits in the context window.
+const MAX_PROMPT_ITEMS: usize = 200;
+
+/// One finding: a TODO-style comment or an unused-code warning.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// `TODO`, `FIXME`, ... or the lint name, e.g. `dead_code`.
+    pub kind: String,
+    pub file: PathBuf,
+    pub line: usize,
+    pub text: String,
+    /// `TODO(name)`, or else whoever last changed the line per `git blame`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// TODO-style markers in `contents`, as `(line, kind, text, named owner)`.
+/// A marker only counts as a whole word, so `TODOS` or `todo_list` don't.
+fn scan_todos(contents: &str) -> Vec<(usize, &'static str, String, Option<String>)> {
+    let mut found = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let hit = TODO_MARKERS.iter().find_map(|&marker| {
+            line.match_indices(marker).find_map(|(at, _)| {
+                let before = line[..at].chars().next_back();
+                let after = line[at + marker.len()..].chars().next();
+                let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+                (!word(before) && !word(after)).then_some((marker, &line[at + marker.len()..]))
+            })
+        });
+        let Some((marker, rest)) = hit else {
+            continue;
+        };
+        let (owner, rest) = match rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+            Some((owner, rest)) => (Some(owner.trim().to_string()), rest),
+            None => (None, rest),
+        };
+        let text = rest.trim_start_matches([':', '-', ' ']).trim_end();
+        let text = text
+            .strip_suffix("*/")
+            .or_else(|| text.strip_suffix("-->"))
+            .unwrap_or(text)
+            .trim();
+        found.push((index + 1, marker, text.to_string(), owner));
+    }
+    found
+}
+
+/// The author of each line of `file` (index 0 is line 1), or nothing for
+/// untracked files.
+fn line_authors(file: &Path) -> Vec<String> {
+    let file = file.to_string_lossy();
+    let Ok(blame) = git::run(&["blame", "--line-porcelain", "--", &file]) else {
+        return Vec::new();
+    };
+    blame
+        .lines()
+        .filter_map(|line| line.strip_prefix("author "))
+        .map(str::to_string)
+        .collect()
+}
+
+/// TODO, FIXME, HACK and XXX comments in the files git tracks.
+pub fn todos() -> Result<Vec<Item>, RuntimeError> {
+    let mut items = Vec::new();
+    for file in git::run(&["ls-files"])?.lines().map(PathBuf::from) {
+        // Binary and unreadable files have no comments to report.
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let found = scan_todos(&contents);
+        if found.is_empty() {
+            continue;
+        }
+        let authors = line_authors(&file);
+        for (line, kind, text, owner) in found {
+            items.push(Item {
+                kind: kind.to_string(),
+                file: file.clone(),
+                line,
+                text,
+                owner: owner.or_else(|| authors.get(line - 1).cloned()),
+            });
+        }
+    }
+    Ok(items)
+}
+
+/// Unused-code warnings from one line of `cargo --message-format=json`.
+fn unused_warning(message: &Value) -> Option<Item> {
+    if message["reason"] != "compiler-message" {
+        return None;
+    }
+    let diagnostic = &message["message"];
+    let lint = diagnostic["code"]["code"].as_str()?;
+    if lint != "dead_code" && !lint.starts_with("unused_") {
+        return None;
+    }
+    let span = diagnostic["spans"]
+        .as_array()?
+        .iter()
+        .find(|span| span["is_primary"] == true)?;
+    Some(Item {
+        kind: lint.to_string(),
+        file: PathBuf::from(span["file_name"].as_str()?),
+        line: span["line_start"].as_u64()? as usize,
+        text: diagnostic["message"].as_str()?.to_string(),
+        owner: None,
+    })
+}
+
+/// Unused code and imports reported by `cargo check` for every target.
+pub fn dead_code() -> Result<Vec<Item>, RuntimeError> {
+    let output = Command::new("cargo")
+        .args(["check", "--all-targets", "--message-format=json"])
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() && stdout.trim().is_empty() {
+        return Err(RuntimeError::Usage(format!(
+            "`cargo check` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // A warning in shared code is reported once per target.
+    let mut seen = BTreeSet::new();
+    let mut items: Vec<Item> = stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter_map(|message| unused_warning(&message))
+        .filter(|item| seen.insert((item.file.clone(), item.line, item.text.clone())))
+        .collect();
+    let mut authors = BTreeMap::new();
+    for item in &mut items {
+        let lines = authors
+            .entry(item.file.clone())
+            .or_insert_with(|| line_authors(&item.file));
+        item.owner = lines.get(item.line - 1).cloned();
+    }
+    Ok(items)
+}
+
+/// How the model's prioritized report is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    /// Groups matching [`json_schema`].
+    Json,
+}
+
+impl ReportFormat {
+    pub const NAMES: [&'static str; 2] = ["markdown", "json"];
+
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "markdown" => Some(ReportFormat::Markdown),
+            "json" => Some(ReportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The shape of a JSON report, sent as Ollama's structured output format.
+pub fn json_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "groups": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "title": { "type": "string" },
+                        "why": { "type": "string" },
+                        "owner": { "type": "string" },
+                        "items": { "type": "array", "items": { "type": "string" } },
+                    },
+                    "required": ["title", "why", "owner", "items"],
+                },
+            },
+        },
+        "required": ["groups"],
+    })
+}
+
+pub fn build_prompt(subject: &str, items: &[Item], format: ReportFormat) -> String {
+    let output = match format {
+        ReportFormat::Markdown => "Write a Markdown report for sprint planning",
+        ReportFormat::Json => {
+            "Reply with a JSON object for sprint planning, whose `groups` are listed most \
+             important first and each have a `title`, `why`, `owner` and `items`"
+        }
+    };
+    let mut prompt = format!(
+        "Here are the {subject} found in this repository. {output}: \
+         group related items, rank the groups by how much they matter (correctness and security \
+         first, then user-facing gaps, then cleanup), say briefly why, and suggest an owner for each \
+         group based on the listed owners. Refer to items as file:line.\n\n"
+    );
+    for item in items.iter().take(MAX_PROMPT_ITEMS) {
+        prompt.push_str(&format!(
+            "- {}:{} {}: {}",
+            item.file.display(),
+            item.line,
+            item.kind,
+            item.text
+        ));
+        if let Some(owner) = &item.owner {
+            prompt.push_str(&format!(" (owner: {owner})"));
+        }
+        prompt.push('\n');
+    }
+    if items.len() > MAX_PROMPT_ITEMS {
+        prompt.push_str(&format!(
+            "- ... and {} more not listed\n",
+            items.len() - MAX_PROMPT_ITEMS
+        ));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_prompt, scan_todos, unused_warning, Item, ReportFormat};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn finds_whole_word_markers_with_optional_owners() {
+        let source = "// TODO: retry on 503\n\
+                      let todo_list = TODOS;\n\
+                      /* FIXME(ana) handle EOF */\n\
+                      # XXX\n";
+
+        assert_eq!(
+            scan_todos(source),
+            vec![
+                (1, "TODO", "retry on 503".to_string(), None),
+                (
+                    3,
+                    "FIXME",
+                    "handle EOF".to_string(),
+                    Some("ana".to_string())
+                ),
+                (4, "XXX", String::new(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_only_unused_code_warnings() {
+        let warning = |lint: &str| {
+            json!({
+                "reason": "compiler-message",
+                "message": {
+                    "message": "function `old` is never used",
+                    "code": { "code": lint },
+                    "spans": [{ "is_primary": true, "file_name": "src/lib.rs", "line_start": 12 }]
+                }
+            })
+        };
+
+        let item = unused_warning(&warning("dead_code")).unwrap();
+        assert_eq!((item.file.to_str(), item.line), (Some("src/lib.rs"), 12));
+        assert!(unused_warning(&warning("unused_imports")).is_some());
+        assert!(unused_warning(&warning("clippy::needless_return")).is_none());
+        assert!(unused_warning(&json!({ "reason": "build-finished" })).is_none());
+    }
+
+    #[test]
+    fn prompt_lists_items_with_owners() {
+        let items = [Item {
+            kind: "TODO".to_string(),
+            file: PathBuf::from("src/lib.rs"),
+            line: 3,
+            text: "retry on 503".to_string(),
+            owner: Some("Ana".to_string()),
+        }];
+
+        assert!(
+            build_prompt("TODO comments", &items, ReportFormat::Markdown)
+                .ends_with("- src/lib.rs:3 TODO: retry on 503 (owner: Ana)\n")
+        );
+    }
+
+    #[test]
+    fn json_prompt_asks_for_ranked_groups() {
+        let prompt = build_prompt("TODO comments", &[], ReportFormat::Json);
+
+        assert!(prompt.contains("JSON object"));
+        assert!(prompt.contains("most important first"));
+        assert!(!prompt.contains("Markdown"));
+        assert_eq!(ReportFormat::from_name("json"), Some(ReportFormat::Json));
+        assert_eq!(ReportFormat::from_name("yaml"), None);
+    }
+}
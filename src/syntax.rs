@@ -0,0 +1,135 @@
+use crate::action;
+use crate::error::RuntimeError;
+use futures_util::future;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Outcome of running generated code through its language's formatter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checked {
+    /// The code parsed; this is the formatted version.
+    Formatted(String),
+    /// The formatter rejected the code with this message.
+    Invalid(String),
+    /// No formatter is known for the file's language.
+    Unchecked,
+}
+
+/// The formatter that parses and reformats `file`'s language from stdin to
+/// stdout.
+fn formatter_for(file: &Path) -> Option<Command> {
+    let mut command = match action::language_for(file)? {
+        "rust" => {
+            let mut command = Command::new("rustfmt");
+            command.args(["--edition", "2021", "--emit", "stdout"]);
+            command
+        }
+        "javascript" | "typescript" => {
+            let mut command = Command::new("prettier");
+            command.arg("--stdin-filepath").arg(file);
+            command
+        }
+        "python" => {
+            let mut command = Command::new("black");
+            command.args(["--quiet", "-"]);
+            command
+        }
+        "go" => Command::new("gofmt"),
+        _ => return None,
+    };
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    Some(command)
+}
+
+/// Runs `code`, the new contents of `file`, through the formatter for its
+/// language. A formatter that isn't installed is a usage error, since the
+/// check was asked for.
+pub async fn check(file: &Path, code: &str) -> Result<Checked, RuntimeError> {
+    let Some(mut command) = formatter_for(file) else {
+        return Ok(Checked::Unchecked);
+    };
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    let mut child = command.spawn().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => RuntimeError::Usage(format!(
+            "checking {} needs `{program}`, which was not found on PATH",
+            file.display()
+        )),
+        _ => RuntimeError::Io(err),
+    })?;
+    // Written while the output is read, so a formatter that streams its
+    // output can't fill the stdout pipe while we are still writing.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let write = async move {
+        // A formatter that fails early may close stdin first; its exit
+        // status says what went wrong.
+        let _ = stdin.write_all(code.as_bytes()).await;
+    };
+    let (_, output) = future::join(write, child.wait_with_output()).await;
+    let output = output?;
+
+    if output.status.success() {
+        Ok(Checked::Formatted(
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+        ))
+    } else {
+        Ok(Checked::Invalid(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, formatter_for, Checked};
+    use crate::error::RuntimeError;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn formatters_are_chosen_by_extension() {
+        let program = |file: &str| {
+            formatter_for(Path::new(file)).map(|command| {
+                command
+                    .as_std()
+                    .get_program()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        };
+
+        assert_eq!(program("src/lib.rs").as_deref(), Some("rustfmt"));
+        assert_eq!(program("web/app.tsx").as_deref(), Some("prettier"));
+        assert_eq!(program("notes.txt"), None);
+        assert_eq!(
+            check(Path::new("notes.txt"), "{").await.unwrap(),
+            Checked::Unchecked
+        );
+    }
+
+    #[tokio::test]
+    async fn rust_is_formatted_or_rejected_with_the_parse_error() {
+        let file = Path::new("src/lib.rs");
+
+        let formatted = match check(file, "fn a( ) { }").await {
+            Err(RuntimeError::Usage(message)) if message.contains("not found on PATH") => {
+                eprintln!("skipping: {message}");
+                return;
+            }
+            result => result.unwrap(),
+        };
+        assert_eq!(formatted, Checked::Formatted("fn a() {}\n".to_string()));
+        let Checked::Invalid(message) = check(file, "fn a( {").await.unwrap() else {
+            panic!("unbalanced code was accepted");
+        };
+        assert!(message.contains("error"));
+    }
+}
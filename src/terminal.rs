@@ -0,0 +1,20 @@
+use std::io::{self, BufRead, Write};
+
+/// Asks a yes/no question on stderr. An empty answer, end of input, or
+/// `assume_yes` picks `default`.
+pub(crate) fn confirm(question: &str, default: bool, assume_yes: bool) -> io::Result<bool> {
+    let options = if default { "[Y/n]" } else { "[y/N]" };
+    eprint!("[ai-coder] {question} {options} ");
+    if assume_yes {
+        eprintln!();
+        return Ok(default);
+    }
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
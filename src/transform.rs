@@ -0,0 +1,475 @@
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Post-processing applied to model output as it arrives, configured per
+/// model under `[models.<name>] transforms = [...]`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    /// Drop the `<think>...</think>` block a reasoning model opens its
+    /// response with.
+    StripThinking,
+    /// Drop a line repeated more than twice in a row, a common failure
+    /// mode of small models stuck in a loop.
+    CollapseRepeats,
+    /// Canonicalize code fence languages (`rs` → `rust`, `py` → `python`).
+    NormalizeFences,
+}
+
+/// Lines identical to the previous one are kept up to this many times.
+const MAX_REPEATS: usize = 2;
+
+trait Stage: Send {
+    /// Consumes more text and returns whatever can be emitted so far.
+    fn push(&mut self, text: &str) -> String;
+    /// Returns anything held back once the stream has ended.
+    fn finish(&mut self) -> String;
+    /// Text the stage removed as model reasoning.
+    fn take_reasoning(&mut self) -> String {
+        String::new()
+    }
+}
+
+/// The configured transforms chained in order.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new(transforms: &[Transform]) -> Self {
+        let stages = transforms
+            .iter()
+            .map(|transform| -> Box<dyn Stage> {
+                match transform {
+                    Transform::StripThinking => Box::<StripThinking>::default(),
+                    Transform::CollapseRepeats => Box::<CollapseRepeats>::default(),
+                    Transform::NormalizeFences => Box::<NormalizeFences>::default(),
+                }
+            })
+            .collect();
+        Pipeline { stages }
+    }
+
+    pub fn push(&mut self, text: &str) -> String {
+        let mut text = text.to_string();
+        for stage in &mut self.stages {
+            text = stage.push(&text);
+        }
+        text
+    }
+
+    pub fn finish(&mut self) -> String {
+        let mut carry = String::new();
+        for stage in &mut self.stages {
+            let mut text = stage.push(&carry);
+            text.push_str(&stage.finish());
+            carry = text;
+        }
+        carry
+    }
+
+    /// Reasoning removed so far by `strip-thinking`.
+    pub fn take_reasoning(&mut self) -> Option<String> {
+        let reasoning: String = self
+            .stages
+            .iter_mut()
+            .map(|stage| stage.take_reasoning())
+            .collect();
+        (!reasoning.is_empty()).then_some(reasoning)
+    }
+}
+
+/// Runs `transforms` over a complete response.
+pub fn apply(transforms: &[Transform], text: &str) -> String {
+    let mut pipeline = Pipeline::new(transforms);
+    let mut output = pipeline.push(text);
+    output.push_str(&pipeline.finish());
+    output
+}
+
+/// A writer that passes everything through a [`Pipeline`]. Call
+/// [`TransformWriter::finish`] at the end of the stream to emit text the
+/// transforms were holding back.
+pub struct TransformWriter<W: Write> {
+    inner: W,
+    pipeline: Pipeline,
+    /// Bytes of a UTF-8 sequence split across writes.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> TransformWriter<W> {
+    pub fn new(inner: W, transforms: &[Transform]) -> Self {
+        TransformWriter {
+            inner,
+            pipeline: Pipeline::new(transforms),
+            partial: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut rest = String::from_utf8_lossy(&self.partial).into_owned();
+        rest = self.pipeline.push(&rest);
+        rest.push_str(&self.pipeline.finish());
+        self.inner.write_all(rest.as_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for TransformWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(err) => err.valid_up_to(),
+        };
+        let text = String::from_utf8_lossy(&self.partial[..valid]).into_owned();
+        self.partial.drain(..valid);
+
+        let output = self.pipeline.push(&text);
+        self.inner.write_all(output.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where [`StripThinking`] is in the response.
+#[derive(Default, PartialEq, Eq)]
+enum ThinkingState {
+    /// Only whitespace so far; a `<think>` here opens a thinking block.
+    #[default]
+    Start,
+    Inside,
+    /// The block has closed; whitespace before the answer is dropped.
+    After,
+    /// No thinking block, or it is over: text passes through unchanged.
+    Answer,
+}
+
+/// Removes a `<think>...</think>` block that opens the response. Tags
+/// anywhere else are part of the answer (e.g. code that mentions them).
+#[derive(Default)]
+struct StripThinking {
+    state: ThinkingState,
+    /// Everything received before the answer started, emitted verbatim if
+    /// it turns out not to be a complete thinking block.
+    raw: String,
+    reasoning: String,
+}
+
+impl Stage for StripThinking {
+    fn push(&mut self, text: &str) -> String {
+        match self.state {
+            ThinkingState::Answer => text.to_string(),
+            ThinkingState::After => {
+                let text = text.trim_start();
+                if !text.is_empty() {
+                    self.state = ThinkingState::Answer;
+                }
+                text.to_string()
+            }
+            ThinkingState::Start => {
+                self.raw.push_str(text);
+                let opening = self.raw.trim_start();
+                if let Some(inside) = opening.strip_prefix("<think>") {
+                    let inside = inside.to_string();
+                    self.raw.truncate(self.raw.len() - inside.len());
+                    self.state = ThinkingState::Inside;
+                    self.push(&inside)
+                } else if "<think>".starts_with(opening) {
+                    String::new()
+                } else {
+                    self.state = ThinkingState::Answer;
+                    std::mem::take(&mut self.raw)
+                }
+            }
+            ThinkingState::Inside => {
+                let seen = self.reasoning.len();
+                self.reasoning.push_str(text);
+                self.raw.push_str(text);
+                // The closing tag may straddle the previous chunk.
+                let mut from = seen.saturating_sub("</think>".len());
+                while !self.reasoning.is_char_boundary(from) {
+                    from -= 1;
+                }
+                let Some(end) = self.reasoning[from..].find("</think>") else {
+                    return String::new();
+                };
+                let end = from + end;
+                let after = self.reasoning[end + "</think>".len()..].to_string();
+                self.reasoning.truncate(end);
+                self.raw.clear();
+                self.state = ThinkingState::After;
+                self.push(&after)
+            }
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        // Text that never became a complete block is part of the answer.
+        if self.state == ThinkingState::Inside {
+            self.reasoning.clear();
+        }
+        std::mem::take(&mut self.raw)
+    }
+
+    fn take_reasoning(&mut self) -> String {
+        if self.state == ThinkingState::Inside {
+            return String::new();
+        }
+        let reasoning = std::mem::take(&mut self.reasoning);
+        reasoning.trim().to_string()
+    }
+}
+
+#[derive(Default)]
+struct CollapseRepeats {
+    line: String,
+    previous: Option<String>,
+    repeats: usize,
+}
+
+impl CollapseRepeats {
+    fn emit(&mut self, line: String, output: &mut String) {
+        if line.trim().is_empty() {
+            self.previous = None;
+        } else if self.previous.as_deref() == Some(line.trim_end()) {
+            self.repeats += 1;
+            if self.repeats > MAX_REPEATS {
+                return;
+            }
+        } else {
+            self.previous = Some(line.trim_end().to_string());
+            self.repeats = 1;
+        }
+        output.push_str(&line);
+    }
+}
+
+impl Stage for CollapseRepeats {
+    fn push(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        for piece in text.split_inclusive('\n') {
+            self.line.push_str(piece);
+            if piece.ends_with('\n') {
+                let line = std::mem::take(&mut self.line);
+                self.emit(line, &mut output);
+            }
+        }
+        output
+    }
+
+    fn finish(&mut self) -> String {
+        let mut output = String::new();
+        let line = std::mem::take(&mut self.line);
+        if !line.is_empty() {
+            self.emit(line, &mut output);
+        }
+        output
+    }
+}
+
+#[derive(Default)]
+struct NormalizeFences {
+    /// Start of the current line while it might still be a fence.
+    line: String,
+    /// The current line is known not to be a fence.
+    passthrough: bool,
+}
+
+fn canonical_language(language: &str) -> Option<&'static str> {
+    let canonical = match language.to_ascii_lowercase().as_str() {
+        "rs" | "rust" => "rust",
+        "py" | "python3" | "python" => "python",
+        "js" | "node" | "javascript" => "javascript",
+        "ts" | "typescript" => "typescript",
+        "sh" | "shell" | "zsh" | "bash" => "bash",
+        "yml" | "yaml" => "yaml",
+        "c++" | "cpp" => "cpp",
+        "golang" | "go" => "go",
+        "rb" | "ruby" => "ruby",
+        _ => return None,
+    };
+    Some(canonical)
+}
+
+fn normalize_fence(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let Some(info) = trimmed.strip_prefix("```") else {
+        return line.to_string();
+    };
+    let language = info.trim_end();
+    match canonical_language(language) {
+        Some(canonical) if canonical != language => {
+            let indent = &line[..line.len() - trimmed.len()];
+            let ending = &info[language.len()..];
+            format!("{indent}```{canonical}{ending}")
+        }
+        _ => line.to_string(),
+    }
+}
+
+impl Stage for NormalizeFences {
+    fn push(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        for ch in text.chars() {
+            if self.passthrough {
+                output.push(ch);
+                self.passthrough = ch != '\n';
+                continue;
+            }
+            self.line.push(ch);
+            if ch == '\n' {
+                output.push_str(&normalize_fence(&self.line));
+                self.line.clear();
+                continue;
+            }
+            let start = self.line.trim_start();
+            let could_be_fence = start.is_empty()
+                || (start.starts_with('`') && (start.len() < 3 || start.starts_with("```")));
+            if !could_be_fence {
+                output.push_str(&self.line);
+                self.line.clear();
+                self.passthrough = true;
+            }
+        }
+        output
+    }
+
+    fn finish(&mut self) -> String {
+        normalize_fence(&std::mem::take(&mut self.line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, Pipeline, Transform, TransformWriter};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::io::Write;
+
+    fn streamed(transforms: &[Transform], chunks: &[&str]) -> String {
+        let mut pipeline = Pipeline::new(transforms);
+        let mut output: String = chunks.iter().map(|chunk| pipeline.push(chunk)).collect();
+        output.push_str(&pipeline.finish());
+        output
+    }
+
+    #[test]
+    fn strips_thinking_even_when_tags_are_split_across_chunks() {
+        let chunks = [
+            "<th",
+            "ink>Let me see",
+            " the code.</thi",
+            "nk>\n\nUse a ",
+            "<b>map</b>.",
+        ];
+
+        let mut pipeline = Pipeline::new(&[Transform::StripThinking]);
+        let mut answer: String = chunks.iter().map(|chunk| pipeline.push(chunk)).collect();
+        answer.push_str(&pipeline.finish());
+
+        assert_eq!(answer, "Use a <b>map</b>.");
+        assert_eq!(
+            pipeline.take_reasoning().as_deref(),
+            Some("Let me see the code.")
+        );
+    }
+
+    #[test]
+    fn keeps_think_tags_that_do_not_open_the_response() {
+        let strip = |text| apply(&[Transform::StripThinking], text);
+
+        assert_eq!(
+            strip("Use `<think>` to mark reasoning.\n<think>x</think>\nDone."),
+            "Use `<think>` to mark reasoning.\n<think>x</think>\nDone."
+        );
+        assert_eq!(
+            strip("  <think>never closed\nfn main() {}\n"),
+            "  <think>never closed\nfn main() {}\n"
+        );
+        assert_eq!(strip("\n<think>plan</think>\n\nfn a() {}"), "fn a() {}");
+        assert_eq!(
+            streamed(&[Transform::StripThinking], &["<thi", "nking is hard"]),
+            "<thinking is hard"
+        );
+    }
+
+    #[test]
+    fn collapses_long_runs_of_identical_lines() {
+        let text = "fn a() {}\nloop\nloop\nloop\nloop\n\nloop\n";
+
+        assert_eq!(
+            streamed(&[Transform::CollapseRepeats], &[&text[..12], &text[12..]]),
+            "fn a() {}\nloop\nloop\n\nloop\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_fence_languages_without_touching_other_lines() {
+        let chunks = ["Here:\n  ``", "`RS\nlet x = `y`;\n```\n```py"];
+
+        assert_eq!(
+            streamed(&[Transform::NormalizeFences], &chunks),
+            "Here:\n  ```rust\nlet x = `y`;\n```\n```python"
+        );
+    }
+
+    #[test]
+    fn writer_applies_transforms_across_split_utf8() {
+        let mut writer = TransformWriter::new(Vec::new(), &[Transform::StripThinking]);
+        let text = "<think>hmm</think>héllo".as_bytes();
+        let split = text.len() - 4; // inside the two-byte 'é'
+
+        writer.write_all(&text[..split]).unwrap();
+        writer.write_all(&text[split..]).unwrap();
+
+        assert_eq!(writer.finish().unwrap(), "héllo".as_bytes());
+    }
+
+    /// Text made of the pieces the transforms react to.
+    fn model_output() -> impl Strategy<Value = String> {
+        let piece = prop_oneof![
+            Just("<think>".to_string()),
+            Just("</think>".to_string()),
+            Just("```rs".to_string()),
+            Just("```".to_string()),
+            Just("\n".to_string()),
+            Just("  ".to_string()),
+            Just("repeat\n".to_string()),
+            "[a-zé<>/`]{0,6}",
+        ];
+        vec(piece, 0..24).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn streaming_matches_transforming_the_whole_text(
+            text in model_output(),
+            cuts in vec(any::<usize>(), 0..6),
+        ) {
+            let transforms = [
+                Transform::StripThinking,
+                Transform::CollapseRepeats,
+                Transform::NormalizeFences,
+            ];
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (text.len() + 1)).collect();
+            cuts.sort();
+
+            // Byte cuts may split 'é'; the writer must reassemble it.
+            let mut writer = TransformWriter::new(Vec::new(), &transforms);
+            let mut start = 0;
+            for end in cuts.into_iter().chain([text.len()]) {
+                writer.write_all(&text.as_bytes()[start..end]).unwrap();
+                start = end;
+            }
+            let streamed = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+            prop_assert_eq!(streamed, apply(&transforms, &text));
+        }
+    }
+}
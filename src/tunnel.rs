@@ -0,0 +1,187 @@
+use crate::error::RuntimeError;
+use reqwest::Url;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// How long to wait for `ssh` to start forwarding.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Pause before restarting a dropped tunnel, so a server that is down
+/// isn't hammered.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Every open tunnel's `ssh`, for [`close_all`].
+static OPEN: Mutex<Vec<Arc<Mutex<Child>>>> = Mutex::new(Vec::new());
+
+/// An `ssh -L` port forward to a model server on another machine. The
+/// tunnel is re-established if `ssh` exits, and closed on drop.
+pub struct Tunnel {
+    /// The forwarded host to use instead of the remote one.
+    pub host: String,
+    child: Arc<Mutex<Child>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// The remote address `host` refers to, as seen from the SSH server. IPv6
+/// addresses keep their brackets, as `ssh -L` expects. Only `http` hosts
+/// can be tunneled: the forward is reached as `127.0.0.1`, which an HTTPS
+/// certificate for the remote name wouldn't match.
+fn forward_target(host: &str) -> Result<(String, u16), RuntimeError> {
+    let url = Url::parse(host)
+        .map_err(|err| RuntimeError::Usage(format!("invalid host `{host}`: {err}")))?;
+    if url.scheme() != "http" {
+        return Err(RuntimeError::Usage(format!(
+            "host `{host}` can't be reached through an SSH tunnel; tunnels carry plain \
+             http, which SSH already encrypts"
+        )));
+    }
+    let target = url.host_str().zip(url.port_or_known_default());
+    target
+        .map(|(name, port)| (name.to_string(), port))
+        .ok_or_else(|| RuntimeError::Usage(format!("host `{host}` has no address to forward")))
+}
+
+fn spawn_ssh(
+    destination: &str,
+    local_port: u16,
+    target: &(String, u16),
+    stderr: Stdio,
+) -> std::io::Result<Child> {
+    Command::new("ssh")
+        .args([
+            "-N",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-o",
+            "BatchMode=yes",
+        ])
+        .args(["-o", "ServerAliveInterval=15", "-L"])
+        .arg(format!("{local_port}:{}:{}", target.0, target.1))
+        .arg(destination)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+}
+
+/// Forwards a free local port through `destination` (`user@gpubox`) to
+/// `host`, which is resolved on the SSH server, and waits until the
+/// forward is accepting connections.
+pub async fn open(destination: &str, host: &str) -> Result<Tunnel, RuntimeError> {
+    let target = forward_target(host)?;
+    let local_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port();
+    let mut child = spawn_ssh(destination, local_port, &target, Stdio::piped())?;
+
+    let started = Instant::now();
+    while TcpStream::connect((Ipv4Addr::LOCALHOST, local_port))
+        .await
+        .is_err()
+    {
+        if let Some(status) = child.try_wait()? {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                pipe.read_to_string(&mut stderr)?;
+            }
+            return Err(RuntimeError::Usage(format!(
+                "ssh to {destination} exited ({status}): {}",
+                stderr.trim()
+            )));
+        }
+        if started.elapsed() > CONNECT_TIMEOUT {
+            let _ = child.kill();
+            return Err(RuntimeError::Usage(format!(
+                "ssh to {destination} did not open the tunnel within {}s",
+                CONNECT_TIMEOUT.as_secs()
+            )));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    // Keep draining ssh's messages so a full pipe can't block it.
+    if let Some(mut pipe) = child.stderr.take() {
+        thread::spawn(move || io::copy(&mut pipe, &mut io::sink()));
+    }
+
+    let tunnel = Tunnel {
+        host: format!("http://127.0.0.1:{local_port}"),
+        child: Arc::new(Mutex::new(child)),
+        closed: Arc::new(AtomicBool::new(false)),
+    };
+    lock(&OPEN).push(Arc::clone(&tunnel.child));
+    let (child, closed) = (Arc::clone(&tunnel.child), Arc::clone(&tunnel.closed));
+    let destination = destination.to_string();
+    thread::spawn(move || {
+        while !closed.load(Ordering::Relaxed) {
+            thread::sleep(POLL_INTERVAL);
+            let exited = matches!(lock(&child).try_wait(), Ok(Some(_)));
+            if !exited || closed.load(Ordering::Relaxed) {
+                continue;
+            }
+            eprintln!("[ai-coder] warning: SSH tunnel to {destination} dropped; reconnecting");
+            thread::sleep(RECONNECT_DELAY);
+            let mut child = lock(&child);
+            if closed.load(Ordering::Relaxed) {
+                return;
+            }
+            match spawn_ssh(&destination, local_port, &target, Stdio::null()) {
+                Ok(restarted) => *child = restarted,
+                Err(err) => {
+                    eprintln!("[ai-coder] warning: could not restart ssh: {err}");
+                    return;
+                }
+            }
+        }
+    });
+    Ok(tunnel)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn kill(child: &Mutex<Child>) {
+    let mut child = lock(child);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Kills every tunnel's `ssh`, for exiting without running destructors.
+pub fn close_all() {
+    for child in lock(&OPEN).drain(..) {
+        kill(&child);
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        lock(&OPEN).retain(|child| !Arc::ptr_eq(child, &self.child));
+        kill(&self.child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::forward_target;
+
+    #[test]
+    fn forwards_to_the_host_as_seen_from_the_ssh_server() {
+        assert_eq!(
+            forward_target("http://localhost:11434").unwrap(),
+            ("localhost".to_string(), 11434)
+        );
+        assert_eq!(
+            forward_target("http://[::1]").unwrap(),
+            ("[::1]".to_string(), 80)
+        );
+        assert!(forward_target("localhost:11434").is_err());
+        assert!(forward_target("https://gpubox:11434").is_err());
+    }
+}
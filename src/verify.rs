@@ -0,0 +1,182 @@
+use crate::error::RuntimeError;
+use crate::AiCoder;
+use std::fmt;
+
+/// How far the model trusts its own answer after checking it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::High => "high",
+            Confidence::Medium => "medium",
+            Confidence::Low => "low",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub confidence: Confidence,
+    /// Claims the check found unsupported or wrong.
+    pub concerns: Vec<String>,
+}
+
+pub fn build_prompt(question: &str, answer: &str) -> String {
+    format!(
+        "Check the answer below against the request it responds to. The request, including any \
+         code or diff in it, is the only source of truth: flag claims about the user's code that it \
+         does not support, as well as mistakes in the answer's own code. \
+         List each problem on its own line starting with \"- \"; list nothing if there are none. \
+         Finish with exactly one line `Confidence: high`, `Confidence: medium` or `Confidence: low`.\n\n\
+         Request:\n<<<\n{question}\n>>>\n\n\
+         Answer:\n<<<\n{answer}\n>>>\n"
+    )
+}
+
+/// Reads the concerns and the final `Confidence:` line of a check. `None`
+/// if the model didn't give a confidence.
+pub fn parse(reply: &str) -> Option<Verdict> {
+    let confidence = reply.lines().rev().find_map(|line| {
+        let line = line.trim().trim_matches(['*', '`']).to_lowercase();
+        match line
+            .strip_prefix("confidence:")?
+            .trim()
+            .trim_matches(['*', '`', '.'])
+        {
+            "high" => Some(Confidence::High),
+            "medium" => Some(Confidence::Medium),
+            "low" => Some(Confidence::Low),
+            _ => None,
+        }
+    })?;
+    let concerns = reply
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("- "))
+        .map(|concern| concern.trim().to_string())
+        .filter(|concern| !concern.is_empty())
+        .collect();
+    Some(Verdict {
+        confidence,
+        concerns,
+    })
+}
+
+/// Asks the model to critique `answer` to `question`.
+pub async fn verify(
+    coder: &AiCoder,
+    question: &str,
+    answer: &str,
+) -> Result<Option<Verdict>, RuntimeError> {
+    let completion = coder.chat(build_prompt(question, answer)).await?;
+    Ok(parse(&completion.text))
+}
+
+/// A Markdown note put above an answer the check rated low, listing its
+/// concerns.
+pub fn warning(verdict: &Verdict) -> String {
+    let mut warning = format!(
+        "> **ai-coder: {} confidence.** Verify claims about your code before relying on them.\n",
+        verdict.confidence
+    );
+    for concern in &verdict.concerns {
+        warning.push_str(&format!("> - {concern}\n"));
+    }
+    warning.push('\n');
+    warning
+}
+
+/// The verdict as an HTML comment, appended to saved responses after the
+/// metadata block.
+pub fn footer(verdict: &Verdict) -> String {
+    let mut footer = format!(
+        "<!-- ai-coder verification\nconfidence: {}\n",
+        verdict.confidence
+    );
+    for concern in &verdict.concerns {
+        footer.push_str(&format!("- {}\n", comment_safe(concern)));
+    }
+    footer.push_str("-->\n");
+    footer
+}
+
+/// `text` with nothing that could end an HTML comment: `>` is escaped and
+/// no two hyphens are left next to each other.
+fn comment_safe(text: &str) -> String {
+    let mut safe = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '>' => safe.push_str("&gt;"),
+            '-' if safe.ends_with('-') => safe.push_str(" -"),
+            _ => safe.push(ch),
+        }
+    }
+    safe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{footer, parse, warning, Confidence, Verdict};
+
+    #[test]
+    fn reads_concerns_and_the_final_confidence() {
+        let reply = "- `parse_config` is never called with a path; the answer assumes it is.\n\
+                     - The loop in the fix never terminates.\n\n\
+                     **Confidence: Low.**\n";
+
+        assert_eq!(
+            parse(reply),
+            Some(Verdict {
+                confidence: Confidence::Low,
+                concerns: vec![
+                    "`parse_config` is never called with a path; the answer assumes it is."
+                        .to_string(),
+                    "The loop in the fix never terminates.".to_string(),
+                ],
+            })
+        );
+        assert_eq!(
+            parse("Confidence: high").unwrap().concerns,
+            Vec::<String>::new()
+        );
+        assert_eq!(parse("Looks fine to me."), None);
+    }
+
+    #[test]
+    fn footer_is_a_closed_html_comment() {
+        let verdict = Verdict {
+            confidence: Confidence::Medium,
+            concerns: vec![
+                "uses --> in a string".to_string(),
+                "prints ---> and --!>".to_string(),
+            ],
+        };
+
+        assert_eq!(
+            footer(&verdict),
+            "<!-- ai-coder verification\nconfidence: medium\n\
+             - uses - -&gt; in a string\n\
+             - prints - - -&gt; and - -!&gt;\n\
+             -->\n"
+        );
+    }
+
+    #[test]
+    fn warning_quotes_the_concerns() {
+        let verdict = Verdict {
+            confidence: Confidence::Low,
+            concerns: vec!["`Config::reload` doesn't exist".to_string()],
+        };
+
+        assert_eq!(
+            warning(&verdict),
+            "> **ai-coder: low confidence.** Verify claims about your code before relying on them.\n\
+             > - `Config::reload` doesn't exist\n\n"
+        );
+    }
+}